@@ -0,0 +1,35 @@
+; Exercises decimal mode arithmetic, printing each result as hex
+; so that BCD values read as their decimal equivalents.
+; Expected output: 47, 02, 99, 41
+
+MAPPED_STDIO_HEX = $4002
+
+  .org $8000
+
+reset:
+  SED
+  CLC
+  LDA #$19
+  ADC #$28 ; 19 + 28 = 47
+  STA MAPPED_STDIO_HEX
+
+  ADC #$55 ; 47 + 55 = 102, leaving 02 and the carry set
+  STA MAPPED_STDIO_HEX
+
+  SBC #$03 ; 02 - 03 = 99, borrowing
+  STA MAPPED_STDIO_HEX
+
+  CLD
+  CLC
+  LDA #$19
+  ADC #$28 ; back to binary: $19 + $28 = $41
+  STA MAPPED_STDIO_HEX
+
+done:
+  JMP done
+
+  .org $fffa
+vectors:
+  .word $0000; NMI
+  .word reset; RESET
+  .word $0000; IRQ
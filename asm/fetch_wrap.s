@@ -0,0 +1,28 @@
+; Runs an instruction whose operand wraps around the top of memory: the JMP
+; at FFFE takes the low byte of its address from FFFF and the high byte from
+; 0000, in RAM. Jumping anywhere else prints nothing.
+; Expected output: 01
+
+MAPPED_STDIO_HEX = $4002
+
+  .org $8000
+
+reset:
+  LDA #$80
+  STA $00 ; high byte of the jump target
+  JMP $fffe
+
+  .org $8010
+target:
+  LDA #$01
+  STA MAPPED_STDIO_HEX
+
+done:
+  JMP done
+
+  .org $fffa
+vectors:
+  .word $0000; NMI
+  .word reset; RESET
+  ; The IRQ vector doubles as the JMP, which is never interrupted
+  .byte $4c, $10
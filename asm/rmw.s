@@ -0,0 +1,36 @@
+; Exercises read-modify-write instructions in absolute,X mode, which must
+; modify the indexed address and leave the PC just past the operand.
+; Expected output: 03, 01, 00, 02
+
+MAPPED_STDIO_HEX = $4002
+
+  .org $8000
+
+reset:
+  LDX #$10
+  LDA #$81
+  STA $1244
+  SEC
+  ROL $1234,X ; $81 rotated through a set carry = $03, carry out
+  LDA $1244
+  STA MAPPED_STDIO_HEX
+
+  LDA #$00
+  ROL A ; the carry out of the ROL above
+  STA MAPPED_STDIO_HEX
+
+  LDA $1234 ; the base address is untouched
+  STA MAPPED_STDIO_HEX
+
+  DEC $1234,X ; $03 - 1 = $02
+  LDA $1244
+  STA MAPPED_STDIO_HEX
+
+done:
+  JMP done
+
+  .org $fffa
+vectors:
+  .word $0000; NMI
+  .word reset; RESET
+  .word $0000; IRQ
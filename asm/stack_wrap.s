@@ -0,0 +1,37 @@
+; Interrupts near the bottom of the stack, where the three bytes pushed by
+; BRK wrap around within page 1 instead of running into page 2.
+; Expected output: FE, 80, 05, 00
+
+MAPPED_STDIO_HEX = $4002
+
+  .org $8000
+
+reset:
+  LDX #$01
+  TXS
+  BRK ; pushes the return address 8005 to 0101 and 0100, then the status to 01FF
+  NOP ; skipped over by the return address
+
+  JMP done
+
+irq:
+  TSX ; three pushes from 01
+  STX MAPPED_STDIO_HEX
+
+  LDA $0101 ; return address high byte
+  STA MAPPED_STDIO_HEX
+
+  LDA $0100 ; return address low byte
+  STA MAPPED_STDIO_HEX
+
+  LDA $0200 ; page 2 is untouched
+  STA MAPPED_STDIO_HEX
+
+done:
+  JMP done
+
+  .org $fffa
+vectors:
+  .word $0000; NMI
+  .word reset; RESET
+  .word irq; IRQ
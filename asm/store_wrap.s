@@ -0,0 +1,63 @@
+; Stores through indexed addresses that run past the top of memory or the
+; end of the zero page, which wrap around into RAM at the bottom instead.
+; Zero page pointers at $FF take their high byte from $00 too.
+; Expected output: 5A, 6B, 7C, 8D, AF, 9E
+
+MAPPED_STDIO_HEX = $4002
+
+  .org $8000
+
+reset:
+  LDX #$01
+  LDA #$5A
+  STA $ffff,X ; writes 0000
+
+  LDX #$02
+  LDA #$6B
+  STA $ff,X ; writes 0001, staying in the zero page
+
+  LDA #$ff
+  STA $10
+  STA $11
+  LDY #$03
+  LDA #$7C
+  STA ($10),Y ; writes 0002
+
+  LDY #$04
+  LDA #$8D
+  STA $ffff,Y ; writes 0003
+
+  LDX #$00
+print:
+  LDA $00,X
+  STA MAPPED_STDIO_HEX
+  INX
+  CPX #$04
+  BNE print
+
+  LDA #$10
+  STA $ff
+  LDA #$02
+  STA $00  ; the pointer at $ff is $0210
+  LDA #$03
+  STA $0100 ; and would be $0310 if it didn't wrap
+  LDY #$01
+  LDA #$9E
+  STA ($ff),Y ; writes 0211
+  LDX #$00
+  LDA #$AF
+  STA ($ff,X) ; writes 0210
+
+  LDA $0210
+  STA MAPPED_STDIO_HEX
+  LDA ($ff),Y ; reads 0211
+  STA MAPPED_STDIO_HEX
+
+done:
+  JMP done
+
+  .org $fffa
+vectors:
+  .word $0000 ; NMI
+  .word reset ; RESET
+  .word $0000 ; IRQ
@@ -0,0 +1,70 @@
+// Measures how fast the core executes instructions
+
+use noentiendo::memory::{BlockMemory, BranchMemory, Charset, ConsoleMemory, Memory};
+use noentiendo::System;
+use std::time::Instant;
+
+const INSTRUCTIONS: u64 = 10_000_000;
+
+// Adds 3 to every byte of page 2, forever
+const PROGRAM: [u8; 17] = [
+  0xA2, 0x00, // LDX #$00
+  0xBD, 0x00, 0x02, // loop: LDA $0200,X
+  0x18, // CLC
+  0x69, 0x03, // ADC #$03
+  0x9D, 0x00, 0x02, // STA $0200,X
+  0xE8, // INX
+  0xD0, 0xF4, // BNE loop
+  0x4C, 0x00, 0x80, // JMP $8000
+];
+
+fn rom() -> BlockMemory {
+  let mut rom = BlockMemory::rom(0x8000);
+  for (address, &value) in PROGRAM.iter().enumerate() {
+    rom.write(address as u16, value);
+  }
+  rom.write(0x7FFD, 0x80); // reset vector
+  rom
+}
+
+// A single writable block, which (unlike RAM) keeps its contents on reset
+fn flat() -> Box<dyn Memory> {
+  let mut memory = BlockMemory::rom(0x10000);
+  let rom = rom();
+  for address in 0x8000..=0xFFFF {
+    memory.write(address, rom.read(address - 0x8000));
+  }
+  Box::new(memory)
+}
+
+fn mapped() -> Box<dyn Memory> {
+  Box::new(
+    BranchMemory::new()
+      .map(0x0000, Box::new(BlockMemory::ram(0x4000)))
+      .map(0x4000, Box::new(ConsoleMemory::new(Charset::Ascii, false)))
+      .map_readonly(0x8000, 0x8000, Box::new(rom())),
+  )
+}
+
+fn run(name: &str, memory: Box<dyn Memory>) {
+  let mut system = System::new(memory);
+  system.reset();
+
+  let start = Instant::now();
+  for _ in 0..INSTRUCTIONS {
+    system.step().unwrap();
+  }
+  let seconds = start.elapsed().as_secs_f64();
+
+  println!(
+    "{}: {:.1}M instructions/s, {:.1}M cycles/s",
+    name,
+    INSTRUCTIONS as f64 / seconds / 1e6,
+    system.cycles() as f64 / seconds / 1e6
+  );
+}
+
+fn main() {
+  run("flat memory", flat());
+  run("mapped memory", mapped());
+}
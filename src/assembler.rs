@@ -0,0 +1,112 @@
+use crate::opcodes::{decode, Mode};
+
+// A minimal assembler for a single instruction at a time, like `LDA #$10`
+// or `STA ($20),Y`. Numbers can be written in hex ($FF), binary (%1010) or
+// decimal. Branch operands are the target address, so the address that the
+// instruction will be placed at is needed to compute the offset.
+pub fn assemble(address: u16, line: &str) -> Result<Vec<u8>, String> {
+  let line = line.split(';').next().unwrap().trim().to_uppercase();
+
+  let (mnemonic, operand) = match line.split_once(char::is_whitespace) {
+    Some((mnemonic, operand)) => (mnemonic, operand.replace(char::is_whitespace, "")),
+    None => (line.as_str(), String::new()),
+  };
+
+  if !(0..=0xFF).any(|opcode| matches!(decode(opcode), Some((name, _)) if name == mnemonic)) {
+    return Err(format!("Unknown instruction {}", mnemonic));
+  }
+
+  for (mode, value) in parse_operand(&operand)? {
+    if let Some(opcode) = (0..=0xFF).find(|&opcode| decode(opcode) == Some((mnemonic, mode))) {
+      return encode(address, opcode, mode, value);
+    }
+  }
+
+  Err(format!("Invalid addressing mode for {}", mnemonic))
+}
+
+// Possible addressing modes for the operand, in order of preference
+fn parse_operand(operand: &str) -> Result<Vec<(Mode, u16)>, String> {
+  if operand.is_empty() {
+    return Ok(vec![(Mode::Implied, 0), (Mode::Accumulator, 0)]);
+  }
+
+  if operand == "A" {
+    return Ok(vec![(Mode::Accumulator, 0)]);
+  }
+
+  if let Some(value) = operand.strip_prefix('#') {
+    let (value, _) = parse_number(value)?;
+    if value > 0xFF {
+      return Err(format!("Immediate value {} doesn't fit in a byte", value));
+    }
+    return Ok(vec![(Mode::Immediate, value)]);
+  }
+
+  if let Some(inner) = operand.strip_prefix('(') {
+    if let Some(pointer) = inner.strip_suffix(",X)") {
+      return Ok(vec![(Mode::IndirectX, parse_number(pointer)?.0)]);
+    }
+    if let Some(pointer) = inner.strip_suffix("),Y") {
+      return Ok(vec![(Mode::IndirectY, parse_number(pointer)?.0)]);
+    }
+    if let Some(pointer) = inner.strip_suffix(')') {
+      return Ok(vec![(Mode::Indirect, parse_number(pointer)?.0)]);
+    }
+    return Err(format!("Invalid operand {}", operand));
+  }
+
+  let (modes, value) = if let Some(value) = operand.strip_suffix(",X") {
+    ([Mode::ZeroPageX, Mode::AbsoluteX], value)
+  } else if let Some(value) = operand.strip_suffix(",Y") {
+    ([Mode::ZeroPageY, Mode::AbsoluteY], value)
+  } else {
+    ([Mode::ZeroPage, Mode::Absolute], operand)
+  };
+
+  let (value, wide) = parse_number(value)?;
+  let mut candidates = Vec::new();
+
+  if modes[0] == Mode::ZeroPage {
+    candidates.push((Mode::Relative, value));
+  }
+  if !wide {
+    candidates.push((modes[0], value));
+  }
+  candidates.push((modes[1], value));
+
+  Ok(candidates)
+}
+
+// Returns the value, and whether it was written as a full word
+fn parse_number(text: &str) -> Result<(u16, bool), String> {
+  let parsed = if let Some(hex) = text.strip_prefix('$') {
+    u16::from_str_radix(hex, 16).map(|value| (value, hex.len() > 2))
+  } else if let Some(binary) = text.strip_prefix('%') {
+    u16::from_str_radix(binary, 2).map(|value| (value, value > 0xFF))
+  } else {
+    text.parse::<u16>().map(|value| (value, value > 0xFF))
+  };
+
+  parsed.map_err(|_| format!("Invalid number {}", text))
+}
+
+fn encode(address: u16, opcode: u8, mode: Mode, value: u16) -> Result<Vec<u8>, String> {
+  match mode {
+    Mode::Implied | Mode::Accumulator => Ok(vec![opcode]),
+    Mode::Relative => {
+      let offset = value.wrapping_sub(address.wrapping_add(2)) as i16;
+      if !(-128..=127).contains(&offset) {
+        return Err(format!("Branch target {:04X} is out of range", value));
+      }
+      Ok(vec![opcode, offset as u8])
+    }
+    _ if mode.length() == 2 => {
+      if value > 0xFF {
+        return Err(format!("Operand {:04X} doesn't fit in a byte", value));
+      }
+      Ok(vec![opcode, value as u8])
+    }
+    _ => Ok(vec![opcode, value as u8, (value >> 8) as u8]),
+  }
+}
@@ -0,0 +1,41 @@
+// Calls back once per audio sample, at a rate that usually isn't a whole
+// number of CPU cycles. The callback is given the cycle the sample falls on.
+pub struct SampleClock {
+  clock_rate: u64,
+  sample_rate: u64,
+  start: u64,
+  samples: u64,
+  callback: Box<dyn FnMut(u64)>,
+}
+
+impl SampleClock {
+  pub fn new(clock_rate: u64, sample_rate: u64, start: u64, callback: Box<dyn FnMut(u64)>) -> Self {
+    Self {
+      clock_rate,
+      sample_rate,
+      start,
+      samples: 0,
+      callback,
+    }
+  }
+
+  // Count the samples from the given cycle instead, as after loading a state
+  // from earlier or later in the run
+  pub fn restart(&mut self, cycles: u64) {
+    self.start = cycles;
+    self.samples = 0;
+  }
+
+  // Emit every sample that falls on or before the given cycle
+  pub fn advance(&mut self, cycles: u64) {
+    loop {
+      let due = self.start + (self.samples + 1) * self.clock_rate / self.sample_rate;
+      if due > cycles {
+        return;
+      }
+
+      self.samples += 1;
+      (self.callback)(due);
+    }
+  }
+}
@@ -0,0 +1,182 @@
+#[cfg(feature = "graphics")]
+use crate::graphics::GraphicsProvider;
+use crate::memory::{BlockMemory, BranchMemory, Memory};
+use crate::system::System;
+#[cfg(feature = "graphics")]
+use std::cell::RefCell;
+#[cfg(feature = "graphics")]
+use std::rc::Rc;
+
+struct Region {
+  start: usize,
+  size: Option<usize>, // devices extend up to the next region
+  readonly: bool,
+  label: String,
+  memory: Box<dyn Memory>,
+}
+
+// Composes a System over a BranchMemory, checking when it's built that no
+// region starts inside another one, which BranchMemory would silently allow
+pub struct SystemBuilder {
+  regions: Vec<Region>,
+  #[cfg(feature = "graphics")]
+  graphics: Option<Rc<RefCell<Box<dyn GraphicsProvider>>>>,
+}
+
+impl SystemBuilder {
+  pub fn new() -> Self {
+    Self {
+      regions: Vec::new(),
+      #[cfg(feature = "graphics")]
+      graphics: None,
+    }
+  }
+
+  pub fn ram(self, start: usize, size: usize) -> Self {
+    self.region(start, Some(size), false, Box::new(BlockMemory::ram(size)))
+  }
+
+  pub fn rom(self, start: usize, data: &[u8]) -> Self {
+    let rom = BlockMemory::from_bytes(data);
+    self.region(start, Some(data.len()), true, Box::new(rom))
+  }
+
+  // A ROM holding the contents of the file, which is reloaded along with the
+  // memory (see Memory::reload)
+  pub fn rom_file(self, start: usize, path: &str) -> Result<Self, String> {
+    let size = std::fs::metadata(path)
+      .map_err(|error| format!("Couldn't read {}: {}", path, error))?
+      .len() as usize;
+    let rom = BlockMemory::from_file(size, path)?;
+    Ok(self.region(start, Some(size), true, Box::new(rom)))
+  }
+
+  pub fn device(self, start: usize, memory: Box<dyn Memory>) -> Self {
+    self.region(start, None, false, memory)
+  }
+
+  // Name the most recently added region
+  pub fn label(mut self, label: &str) -> Self {
+    if let Some(region) = self.regions.last_mut() {
+      region.label = label.to_string();
+    }
+
+    self
+  }
+
+  // The provider the devices draw to, for System::run_frame
+  #[cfg(feature = "graphics")]
+  pub fn graphics(mut self, graphics: Rc<RefCell<Box<dyn GraphicsProvider>>>) -> Self {
+    self.graphics = Some(graphics);
+    self
+  }
+
+  pub fn build(self) -> Result<System, String> {
+    #[cfg(feature = "graphics")]
+    let graphics = self.graphics.clone();
+
+    #[allow(unused_mut)]
+    let mut system = System::new(Box::new(self.build_memory()?));
+
+    #[cfg(feature = "graphics")]
+    if let Some(graphics) = graphics {
+      system.graphics(graphics);
+    }
+
+    Ok(system)
+  }
+
+  // Just the memory map, for a System set up by other means
+  pub fn build_memory(self) -> Result<BranchMemory, String> {
+    for region in &self.regions {
+      if region.start + region.size.unwrap_or(1) > 0x10000 {
+        return Err(format!(
+          "The {} at {:04X} extends past the end of memory",
+          name(region),
+          region.start
+        ));
+      }
+    }
+
+    for (index, region) in self.regions.iter().enumerate() {
+      for other in &self.regions[index + 1..] {
+        let (first, second) = match region.start <= other.start {
+          true => (region, other),
+          false => (other, region),
+        };
+
+        if second.start < first.start + first.size.unwrap_or(1) {
+          return Err(format!(
+            "The {} at {:04X} overlaps the {} at {:04X}",
+            name(second),
+            second.start,
+            name(first),
+            first.start
+          ));
+        }
+      }
+    }
+
+    let mut memory = BranchMemory::new();
+    for region in self.regions {
+      memory = match (region.readonly, region.size) {
+        (true, Some(size)) => memory.map_readonly(region.start, size, region.memory),
+        (false, Some(size)) => memory.map_sized(region.start, size, region.memory),
+        (_, None) => memory.map(region.start, region.memory),
+      }
+      .label(&region.label);
+    }
+
+    Ok(memory)
+  }
+
+  fn region(
+    mut self,
+    start: usize,
+    size: Option<usize>,
+    readonly: bool,
+    memory: Box<dyn Memory>,
+  ) -> Self {
+    self.regions.push(Region {
+      start,
+      size,
+      readonly,
+      label: String::new(),
+      memory,
+    });
+
+    self
+  }
+}
+
+fn name(region: &Region) -> &str {
+  match region.label.as_str() {
+    "" => "region",
+    label => label,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn memory_past_a_ram_block_is_unmapped() {
+    let mut memory = SystemBuilder::new()
+      .ram(0x0000, 0x0100)
+      .rom(0x8000, &[0xEA; 0x10])
+      .build_memory()
+      .unwrap();
+
+    memory.write(0x0000, 0x42);
+    assert_eq!(memory.read(0x0000), 0x42);
+    assert!(memory.mapped(0x00FF));
+
+    // Rather than a mirror of the RAM
+    assert!(!memory.mapped(0x0100));
+    assert!(memory.try_read(0x0100).is_err());
+    assert_eq!(memory.read(0x0100), 0x00);
+    assert!(!memory.mapped(0x7FFF));
+    assert!(memory.mapped(0x8000));
+  }
+}
@@ -0,0 +1,199 @@
+use crate::system::System;
+use std::io::Write;
+
+// A simple command-line debugger, driven over stdin:
+//   step [count]         execute instructions (default 1)
+//   continue             run until a breakpoint is hit
+//   finish               run until the current subroutine returns
+//   break <addr>         toggle a breakpoint
+//   reg [<name> <value>] show registers, or set one (a, x, y, sp, pc, sr)
+//   mem <addr> [len]     dump memory
+//   dis <addr> [len]     disassemble memory
+//   list [count]         disassemble around the PC (default 5 either side)
+//   backtrace            guess at the subroutine calls that led here
+//   vectors              show the interrupt vectors
+//   quit
+pub struct Debugger {}
+
+impl Debugger {
+  pub fn new() -> Self {
+    Self {}
+  }
+
+  pub fn run(&mut self, system: &mut System) {
+    print_registers(system);
+
+    loop {
+      print!("(debug) ");
+      std::io::stdout().flush().unwrap();
+
+      let mut line = String::new();
+      if std::io::stdin().read_line(&mut line).unwrap() == 0 {
+        return;
+      }
+
+      let words: Vec<&str> = line.split_whitespace().collect();
+      let result = match words.as_slice() {
+        [] => Ok(()),
+        ["q" | "quit"] => return,
+        ["s" | "step"] => self.step(system, 1),
+        ["s" | "step", count] => parse_number(count).and_then(|n| self.step(system, n)),
+        ["c" | "continue"] => self.resume(system),
+        ["f" | "finish"] => self.finish(system),
+        ["v" | "vectors"] => {
+          let vectors = system.vectors();
+          println!(
+            "NMI={:04X} RESET={:04X} IRQ={:04X}",
+            vectors.nmi, vectors.reset, vectors.irq
+          );
+          Ok(())
+        }
+        ["b" | "break", address] => parse_address(address).map(|address| toggle(system, address)),
+        ["r" | "reg"] => {
+          print_registers(system);
+          Ok(())
+        }
+        ["r" | "reg", name, value] => set_register(system, name, value),
+        ["m" | "mem", address] => parse_address(address).map(|address| dump(system, address, 16)),
+        ["m" | "mem", address, len] => parse_address(address)
+          .and_then(|address| parse_number(len).map(|len| dump(system, address, len))),
+        ["d" | "dis", address] => {
+          parse_address(address).map(|address| disassemble(system, address, 16))
+        }
+        ["d" | "dis", address, len] => parse_address(address)
+          .and_then(|address| parse_number(len).map(|len| disassemble(system, address, len))),
+        ["l" | "list"] => {
+          list(system, 5);
+          Ok(())
+        }
+        ["l" | "list", count] => parse_number(count).map(|count| list(system, count)),
+        ["bt" | "backtrace"] => {
+          backtrace(system);
+          Ok(())
+        }
+        _ => Err(format!("Unknown command: {}", line.trim())),
+      };
+
+      if let Err(error) = result {
+        println!("{}", error);
+      }
+    }
+  }
+
+  fn step(&mut self, system: &mut System, count: u16) -> Result<(), String> {
+    for _ in 0..count {
+      system.try_tick().map_err(|error| error.to_string())?;
+      println!("{}", system.last_instruction());
+    }
+
+    print_registers(system);
+    Ok(())
+  }
+
+  fn finish(&mut self, system: &mut System) -> Result<(), String> {
+    system.run_to_return().map_err(|error| error.to_string())?;
+
+    print_registers(system);
+    Ok(())
+  }
+
+  fn resume(&mut self, system: &mut System) -> Result<(), String> {
+    system
+      .run_to_breakpoint()
+      .map_err(|error| error.to_string())?;
+
+    println!("Hit breakpoint");
+    print_registers(system);
+    Ok(())
+  }
+}
+
+fn toggle(system: &mut System, address: u16) {
+  if system.remove_breakpoint(address) {
+    println!("Removed breakpoint at {:04X}", address);
+  } else {
+    system.add_breakpoint(address);
+    println!("Added breakpoint at {:04X}", address);
+  }
+}
+
+fn print_registers(system: &System) {
+  println!("{}", system.registers);
+}
+
+fn set_register(system: &mut System, name: &str, value: &str) -> Result<(), String> {
+  let value = parse_address(value)?;
+  let byte = || u8::try_from(value).map_err(|_| format!("{:X} doesn't fit in {}", value, name));
+  let registers = &mut system.registers;
+
+  match name.to_lowercase().as_str() {
+    "a" => registers.a = byte()?,
+    "x" => registers.x = byte()?,
+    "y" => registers.y = byte()?,
+    "sp" => registers.sp.set(byte()?),
+    "sr" => registers.sr.load(byte()?),
+    "pc" => registers.pc.load(value),
+    _ => return Err(format!("Unknown register: {}", name)),
+  }
+
+  print_registers(system);
+  Ok(())
+}
+
+fn disassemble(system: &System, address: u16, len: u16) {
+  if len == 0 {
+    return;
+  }
+
+  let end = address.saturating_add(len - 1);
+  for (address, text) in system.disassemble_range(address, end) {
+    println!("{:04X}: {}", address, text);
+  }
+}
+
+// The instructions before the PC are a best guess, and may start out wrong
+fn list(system: &System, count: u16) {
+  let pc = system.registers.pc.address();
+
+  for (address, text) in system.disassemble_around(count, count) {
+    let marker = if address == pc { ">" } else { " " };
+    println!("{} {:04X}: {}", marker, address, text);
+  }
+}
+
+// A JSR in the last two bytes of memory wraps around, which leaves nothing
+// to disassemble, so just its address is shown
+fn backtrace(system: &System) {
+  for (depth, address) in system.call_stack().into_iter().enumerate() {
+    match system
+      .disassemble_range(address, address.wrapping_add(2))
+      .first()
+    {
+      Some((_, text)) => println!("#{} {:04X}: {}", depth, address, text),
+      None => println!("#{} {:04X}", depth, address),
+    }
+  }
+}
+
+fn dump(system: &System, address: u16, len: u16) {
+  for row in (0..len).step_by(16) {
+    let start = address.wrapping_add(row);
+    let bytes: Vec<String> = (row..len.min(row.saturating_add(16)))
+      .map(|offset| format!("{:02X}", system.peek(address.wrapping_add(offset))))
+      .collect();
+
+    println!("{:04X}: {}", start, bytes.join(" "));
+  }
+}
+
+// Addresses and register values are given in hex, with an optional `$`
+fn parse_address(text: &str) -> Result<u16, String> {
+  u16::from_str_radix(text.trim_start_matches('$'), 16)
+    .map_err(|_| format!("Invalid address: {}", text))
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+  text
+    .parse()
+    .map_err(|_| format!("Invalid number: {}", text))
+}
@@ -0,0 +1,239 @@
+use crate::cycles::CYCLES;
+use crate::memory::Memory;
+use crate::opcodes::{decode, Mode};
+use std::collections::HashMap;
+
+// Names for addresses, like CHROUT for $FFD2
+pub type Labels = HashMap<u16, String>;
+
+// Disassemble the instruction made up of the given bytes (the opcode followed
+// by up to two operand bytes) located at the given address, returning the
+// text and the instruction's length
+pub fn disassemble(address: u16, bytes: [u8; 3]) -> (String, u16) {
+  disassemble_labeled(address, bytes, &Labels::new())
+}
+
+// Like disassemble, but with operand addresses and branch targets shown by
+// name where they have a label
+pub fn disassemble_labeled(address: u16, bytes: [u8; 3], labels: &Labels) -> (String, u16) {
+  let (mnemonic, mode) = match decode(bytes[0]) {
+    Some(decoded) => decoded,
+    None => return (format!(".byte ${:02X}", bytes[0]), 1),
+  };
+
+  let byte = bytes[1];
+  let word = (bytes[2] as u16) << 8 | bytes[1] as u16;
+
+  let zero_page = || name(labels, byte as u16, format!("${:02X}", byte));
+  let absolute = || name(labels, word, format!("${:04X}", word));
+
+  let operand = match mode {
+    Mode::Implied => String::new(),
+    Mode::Accumulator => "A".to_string(),
+    Mode::Immediate => format!("#${:02X}", byte),
+    Mode::ZeroPage => zero_page(),
+    Mode::ZeroPageX => format!("{},X", zero_page()),
+    Mode::ZeroPageY => format!("{},Y", zero_page()),
+    Mode::Absolute => absolute(),
+    Mode::AbsoluteX => format!("{},X", absolute()),
+    Mode::AbsoluteY => format!("{},Y", absolute()),
+    Mode::Indirect => format!("({})", absolute()),
+    Mode::IndirectX => format!("({},X)", zero_page()),
+    Mode::IndirectY => format!("({}),Y", zero_page()),
+    Mode::Relative => {
+      let target = address.wrapping_add(2).wrapping_add(byte as i8 as u16);
+      name(labels, target, format!("${:04X}", target))
+    }
+  };
+
+  let text = if operand.is_empty() {
+    mnemonic.to_string()
+  } else {
+    format!("{} {}", mnemonic, operand)
+  };
+
+  (text, mode.length())
+}
+
+fn name(labels: &Labels, address: u16, hex: String) -> String {
+  labels.get(&address).cloned().unwrap_or(hex)
+}
+
+// The opcode's base cycle count and what can add to it, like "4 (+1 page)"
+// for a load that takes longer when indexing crosses a page
+fn timing(opcode: u8) -> Option<String> {
+  let (mnemonic, mode) = decode(opcode)?;
+
+  // Stores and read-modify-writes always take the extra cycle, so it's
+  // already in their base count
+  let fixed = matches!(
+    mnemonic,
+    "STA" | "STX" | "STY" | "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC"
+  );
+
+  let penalty = match mode {
+    Mode::Relative => " (+1 taken, +1 page)",
+    Mode::AbsoluteX | Mode::AbsoluteY | Mode::IndirectY if !fixed => " (+1 page)",
+    _ => "",
+  };
+
+  Some(format!("{}{}", CYCLES[opcode as usize], penalty))
+}
+
+// Disassemble every instruction from start to end (inclusive), following the
+// instruction lengths from the start so operand bytes are never mistaken for
+// opcodes. An instruction cut off by the end of the range is listed as bytes.
+// With cycles, each instruction is followed by its timing, as a comment.
+pub fn disassemble_range(
+  memory: &dyn Memory,
+  start: u16,
+  end: u16,
+  labels: &Labels,
+  cycles: bool,
+) -> Vec<(u16, String)> {
+  let mut listing = Vec::new();
+  let mut address = start as u32;
+
+  while address <= end as u32 {
+    let remaining = end as u32 - address + 1;
+
+    let mut bytes = [0; 3];
+    for (offset, byte) in bytes.iter_mut().enumerate().take(remaining as usize) {
+      *byte = memory.peek((address + offset as u32) as u16);
+    }
+
+    let (text, length) = disassemble_labeled(address as u16, bytes, labels);
+
+    if length as u32 > remaining {
+      for (offset, byte) in bytes.iter().enumerate().take(remaining as usize) {
+        listing.push((
+          (address + offset as u32) as u16,
+          format!(".byte ${:02X}", byte),
+        ));
+      }
+      break;
+    }
+
+    let text = match timing(bytes[0]) {
+      Some(timing) if cycles => format!("{:<16} ; {}", text, timing),
+      _ => text,
+    };
+
+    listing.push((address as u16, text));
+    address += length as u32;
+  }
+
+  listing
+}
+
+// A printable listing of disassemble_range, one instruction per line with
+// its address and bytes, and labels on lines of their own
+pub fn listing(memory: &dyn Memory, start: u16, end: u16, labels: &Labels, cycles: bool) -> String {
+  let instructions = disassemble_range(memory, start, end, labels, cycles);
+  let mut text = String::new();
+
+  for (index, (address, instruction)) in instructions.iter().enumerate() {
+    let next = match instructions.get(index + 1) {
+      Some(&(next, _)) => next as u32,
+      None => end as u32 + 1,
+    };
+
+    let bytes: Vec<String> = (*address as u32..next)
+      .map(|byte| format!("{:02X}", memory.peek(byte as u16)))
+      .collect();
+
+    if let Some(label) = labels.get(address) {
+      text.push_str(&format!("{}:\n", label));
+    }
+    text.push_str(&format!(
+      "{:04X}  {:<8}  {}\n",
+      address,
+      bytes.join(" "),
+      instruction
+    ));
+  }
+
+  text
+}
+
+// Disassemble up to `before` instructions leading up to the PC, then the
+// instruction at the PC and `after` more following it.
+//
+// Going backwards is ambiguous, since operand bytes look like opcodes too, so
+// this tries each start address in a window before the PC and keeps the
+// earliest one whose instructions are all valid and end exactly at the PC.
+// Decoding from a wrong start usually falls into step with the real
+// instructions within a few bytes, so the entries nearest the PC are the most
+// trustworthy. It can still be fooled, for example just after data or a jump
+// that's never fallen through, or by an operand that happens to decode to a
+// run of instructions ending at the PC. If no start fits, the bytes before
+// the PC are listed as data.
+pub fn disassemble_around(
+  memory: &dyn Memory,
+  pc: u16,
+  before: u16,
+  after: u16,
+  labels: &Labels,
+) -> Vec<(u16, String)> {
+  let mut listing = disassemble_before(memory, pc, before, labels);
+  let mut address = pc;
+
+  for _ in 0..=after {
+    let (text, length) = disassemble_labeled(address, peek_bytes(memory, address), labels);
+    listing.push((address, text));
+    address = address.wrapping_add(length);
+  }
+
+  listing
+}
+
+fn disassemble_before(
+  memory: &dyn Memory,
+  pc: u16,
+  count: u16,
+  labels: &Labels,
+) -> Vec<(u16, String)> {
+  // Instructions are at most 3 bytes long
+  let window = count.saturating_mul(3).min(pc);
+
+  for start in (pc - window)..pc {
+    if let Some(listing) = disassemble_to(memory, start, pc, labels) {
+      let skip = listing.len().saturating_sub(count as usize);
+      return listing.into_iter().skip(skip).collect();
+    }
+  }
+
+  ((pc - count.min(pc))..pc)
+    .map(|address| (address, format!(".byte ${:02X}", memory.peek(address))))
+    .collect()
+}
+
+// The instructions from start onwards, if they're all valid opcodes and the
+// last one ends exactly at the given address
+fn disassemble_to(
+  memory: &dyn Memory,
+  start: u16,
+  end: u16,
+  labels: &Labels,
+) -> Option<Vec<(u16, String)>> {
+  let mut listing = Vec::new();
+  let mut address = start as u32;
+
+  while address < end as u32 {
+    let bytes = peek_bytes(memory, address as u16);
+    decode(bytes[0])?;
+
+    let (text, length) = disassemble_labeled(address as u16, bytes, labels);
+    listing.push((address as u16, text));
+    address += length as u32;
+  }
+
+  match address == end as u32 {
+    true => Some(listing),
+    false => None,
+  }
+}
+
+fn peek_bytes(memory: &dyn Memory, address: u16) -> [u8; 3] {
+  [0, 1, 2].map(|offset| memory.peek(address.wrapping_add(offset)))
+}
@@ -1,18 +1,19 @@
 use crate::fetch::Fetch;
-use crate::registers::{flags, ALU};
-use crate::system::{InterruptHandler, MemoryIO, Stack, System};
+use crate::opcodes::jams;
+use crate::registers::{flags, StatusRegister, ALU};
+use crate::system::{CpuError, InterruptHandler, MemoryIO, Stack, System};
 
 pub trait Execute {
-  fn execute(&mut self, opcode: u8) -> Result<(), ()>;
+  fn execute(&mut self, opcode: u8) -> Result<(), CpuError>;
 }
 
 impl Execute for System {
-  fn execute(&mut self, opcode: u8) -> Result<(), ()> {
+  fn execute(&mut self, opcode: u8) -> Result<(), CpuError> {
     match opcode {
       // === LOAD ===
       0xA1 | 0xA5 | 0xA9 | 0xAD | 0xB1 | 0xB5 | 0xB9 | 0xBD => {
         // LDA
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.a = value;
         self.registers.sr.set_nz(value);
         Ok(())
@@ -20,7 +21,7 @@ impl Execute for System {
 
       0xA2 | 0xA6 | 0xAE | 0xB6 | 0xBE => {
         // LDX
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.x = value;
         self.registers.sr.set_nz(value);
         Ok(())
@@ -28,7 +29,7 @@ impl Execute for System {
 
       0xA0 | 0xA4 | 0xAC | 0xB4 | 0xBC => {
         // LDY
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.y = value;
         self.registers.sr.set_nz(value);
         Ok(())
@@ -37,26 +38,31 @@ impl Execute for System {
       // === STORE ===
       0x81 | 0x85 | 0x8D | 0x91 | 0x95 | 0x99 | 0x9D => {
         // STA
-        let address = self.fetch_operand_address(opcode);
+        let address = self.fetch_operand_address(opcode)?;
         self.write(address, self.registers.a);
+        self.note_transfer(self.registers.a);
         Ok(())
       }
 
       // STX
       0x86 | 0x8E | 0x96 => {
-        let address = self.fetch_operand_address(opcode);
+        let address = self.fetch_operand_address(opcode)?;
         self.write(address, self.registers.x);
+        self.note_transfer(self.registers.x);
         Ok(())
       }
 
       // STY
       0x84 | 0x8C | 0x94 => {
-        let address = self.fetch_operand_address(opcode);
+        let address = self.fetch_operand_address(opcode)?;
         self.write(address, self.registers.y);
+        self.note_transfer(self.registers.y);
         Ok(())
       }
 
       // === TRANSFER ===
+      // Every transfer sets N and Z from the value moved, except TXS, which
+      // leaves the flags alone (TSX going the other way does set them)
       0xAA => {
         // TAX
         self.registers.x = self.registers.a;
@@ -82,7 +88,7 @@ impl Execute for System {
         Ok(())
       }
       0x9A => {
-        // TXS
+        // TXS (no flags)
         self.registers.sp.set(self.registers.x);
         Ok(())
       }
@@ -101,7 +107,7 @@ impl Execute for System {
       }
       0x08 => {
         // PHP
-        self.push(self.registers.sr.get());
+        self.push(self.registers.sr.pushed_value(true));
         Ok(())
       }
       0x68 => {
@@ -112,7 +118,7 @@ impl Execute for System {
       0x28 => {
         // PLP
         let status = self.pop();
-        self.registers.sr.load(status);
+        self.registers.sr = StatusRegister::from_pulled(status);
         Ok(())
       }
 
@@ -128,13 +134,12 @@ impl Execute for System {
       }
       0x06 | 0x0E | 0x16 | 0x1E => {
         // ASL
-        let address = self.fetch_operand_address(opcode);
-        let value = self.read(address);
+        let (address, value) = self.fetch_operand_rmw(opcode)?;
         let result = value << 1;
 
         self.registers.sr.write(flags::CARRY, value & 0x80 != 0);
         self.registers.sr.set_nz(result);
-        self.write(address, result);
+        self.modify(address, value, result);
         Ok(())
       }
 
@@ -149,13 +154,12 @@ impl Execute for System {
       }
       0x46 | 0x4E | 0x56 | 0x5E => {
         // LSR
-        let address = self.fetch_operand_address(opcode);
-        let value = self.read(address);
+        let (address, value) = self.fetch_operand_rmw(opcode)?;
         let result = value >> 1;
 
         self.registers.sr.write(flags::CARRY, value & 0x01 != 0);
         self.registers.sr.set_nz(result);
-        self.write(address, result);
+        self.modify(address, value, result);
         Ok(())
       }
 
@@ -171,13 +175,12 @@ impl Execute for System {
       }
       0x26 | 0x2E | 0x36 | 0x3E => {
         // ROL
-        let address = self.fetch_operand_address(opcode);
-        let value = self.read(address);
+        let (address, value) = self.fetch_operand_rmw(opcode)?;
         let result = (value << 1) | (self.registers.sr.read(flags::CARRY) as u8);
 
         self.registers.sr.write(flags::CARRY, value & 0x80 != 0);
         self.registers.sr.set_nz(result);
-        self.write(address, result);
+        self.modify(address, value, result);
         Ok(())
       }
 
@@ -193,20 +196,19 @@ impl Execute for System {
       }
       0x66 | 0x6E | 0x76 | 0x7E => {
         // ROR
-        let address = self.fetch_operand_address(opcode);
-        let value = self.read(address);
+        let (address, value) = self.fetch_operand_rmw(opcode)?;
         let result = value >> 1 | (self.registers.sr.read(flags::CARRY) as u8) << 7;
 
         self.registers.sr.write(flags::CARRY, value & 0x01 != 0);
         self.registers.sr.set_nz(result);
-        self.write(address, result);
+        self.modify(address, value, result);
         Ok(())
       }
 
       // === LOGIC ===
       0x21 | 0x25 | 0x29 | 0x2D | 0x31 | 0x35 | 0x39 | 0x3D => {
         // AND
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.a &= value;
         self.registers.sr.set_nz(self.registers.a);
         Ok(())
@@ -214,7 +216,7 @@ impl Execute for System {
 
       0x24 | 0x2C => {
         // BIT
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.sr.write(flags::NEGATIVE, value & 0x80 != 0);
         self.registers.sr.write(flags::OVERFLOW, value & 0x40 != 0);
         self
@@ -226,7 +228,7 @@ impl Execute for System {
 
       0x41 | 0x45 | 0x49 | 0x4D | 0x51 | 0x55 | 0x59 | 0x5D => {
         // EOR
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.a ^= value;
         self.registers.sr.set_nz(self.registers.a);
         Ok(())
@@ -234,7 +236,7 @@ impl Execute for System {
 
       0x01 | 0x05 | 0x09 | 0x0D | 0x11 | 0x15 | 0x19 | 0x1D => {
         // ORA
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.a |= value;
         self.registers.sr.set_nz(self.registers.a);
         Ok(())
@@ -243,47 +245,48 @@ impl Execute for System {
       // === ARITHMETIC ===
       0x61 | 0x65 | 0x69 | 0x6D | 0x71 | 0x75 | 0x79 | 0x7D => {
         // ADC
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.alu_add(value);
+        self.decimal_flags();
         Ok(())
       }
 
       0xC1 | 0xC5 | 0xC9 | 0xCD | 0xD1 | 0xD5 | 0xD9 | 0xDD => {
         // CMP
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.alu_compare(self.registers.a, value);
         Ok(())
       }
 
       0xE0 | 0xE4 | 0xEC => {
         // CPX
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.alu_compare(self.registers.x, value);
         Ok(())
       }
 
       0xC0 | 0xC4 | 0xCC => {
         // CPY
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.alu_compare(self.registers.y, value);
         Ok(())
       }
 
       0xE1 | 0xE5 | 0xE9 | 0xED | 0xF1 | 0xF5 | 0xF9 | 0xFD => {
         // SBC
-        let value = self.fetch_operand_value(opcode);
+        let value = self.fetch_operand_value(opcode)?;
         self.registers.alu_subtract(value);
+        self.decimal_flags();
         Ok(())
       }
 
       // === INCREMENT ===
       0xC6 | 0xCE | 0xD6 | 0xDE => {
         // DEC
-        let address = self.fetch_operand_address(opcode);
-        let value = self.read(address);
+        let (address, value) = self.fetch_operand_rmw(opcode)?;
         let result = value.wrapping_sub(1);
         self.registers.sr.set_nz(result);
-        self.write(address, result);
+        self.modify(address, value, result);
         Ok(())
       }
 
@@ -303,11 +306,10 @@ impl Execute for System {
 
       0xE6 | 0xEE | 0xF6 | 0xFE => {
         // INC
-        let address = self.fetch_operand_address(opcode);
-        let value = self.read(address);
+        let (address, value) = self.fetch_operand_rmw(opcode)?;
         let result = value.wrapping_add(1);
         self.registers.sr.set_nz(result);
-        self.write(address, result);
+        self.modify(address, value, result);
         Ok(())
       }
 
@@ -328,27 +330,35 @@ impl Execute for System {
       // === CONTROL ===
       0x00 => {
         // BRK
+        // ROMs without an IRQ handler leave the vector zeroed, and use BRK to
+        // end the program
+        if self.read_word(0xFFFE) == 0x0000 {
+          return Err(CpuError::Break);
+        }
+
         self.registers.pc.increment();
-        self.interrupt(true);
+        self.interrupt(true, true);
         Ok(())
       }
       0x4C | 0x6C => {
         // JMP
         let address = match opcode {
-          0x4C => self.fetch_word(),
+          0x4C => self.fetch_word()?,
           0x6C => {
-            let indirect = self.fetch_word();
-            self.read_word(indirect)
+            let indirect = self.fetch_word()?;
+            self.try_read_word(indirect)?
           }
           _ => unreachable!(),
         };
 
+        self.note_address(address);
         self.registers.pc.load(address);
         Ok(())
       }
       0x20 => {
         // JSR absolute
-        let address = self.fetch_word();
+        let address = self.fetch_word()?;
+        self.note_address(address);
         self.push_word(self.registers.pc.address().wrapping_sub(1));
         self.registers.pc.load(address);
         Ok(())
@@ -356,7 +366,7 @@ impl Execute for System {
       0x40 => {
         // RTI
         let status = self.pop();
-        self.registers.sr.load(status);
+        self.registers.sr = StatusRegister::from_pulled(status);
         let dest = self.pop_word();
         self.registers.pc.load(dest);
         Ok(())
@@ -370,7 +380,7 @@ impl Execute for System {
 
       // === BRANCH ===
       0x90 | 0xB0 | 0xF0 | 0x30 | 0xD0 | 0x10 | 0x50 | 0x70 => {
-        let offset = self.fetch() as i8;
+        let offset = self.fetch()? as i8;
 
         let condition = match opcode {
           0x90 => !self.registers.sr.read(flags::CARRY),   // BCC
@@ -385,7 +395,7 @@ impl Execute for System {
         };
 
         if condition {
-          self.registers.pc.offset(offset);
+          self.take_branch(offset);
         }
 
         Ok(())
@@ -421,10 +431,12 @@ impl Execute for System {
         Ok(())
       }
 
-      _ => {
-        println!("Unimplemented opcode: {:02X}", opcode);
-        Err(())
-      }
+      _ if jams(opcode) => Err(CpuError::Jam { opcode }),
+
+      _ => Err(CpuError::UnimplementedOpcode {
+        opcode,
+        pc: self.registers.pc.address().wrapping_sub(1),
+      }),
     }
   }
 }
@@ -1,97 +1,149 @@
-use crate::system::{MemoryIO, System};
+use crate::system::{CpuError, MemoryIO, System};
 
 pub trait Fetch {
   // Fetch immediate values
-  fn fetch(&mut self) -> u8;
-  fn fetch_word(&mut self) -> u16;
+  fn fetch(&mut self) -> Result<u8, CpuError>;
+  fn fetch_word(&mut self) -> Result<u16, CpuError>;
 
   // Fetch operand value based on the opcode
-  fn fetch_operand_value(&mut self, opcode: u8) -> u8;
+  fn fetch_operand_value(&mut self, opcode: u8) -> Result<u8, CpuError>;
 
   // Fetch operand address based on the opcode
-  fn fetch_operand_address(&mut self, opcode: u8) -> u16;
+  fn fetch_operand_address(&mut self, opcode: u8) -> Result<u16, CpuError>;
+
+  // Fetch both the operand address and its value for read-modify-write
+  // instructions, reading the value exactly once
+  fn fetch_operand_rmw(&mut self, opcode: u8) -> Result<(u16, u8), CpuError>;
+
+  // The index register added to a 16-bit address, for the modes where that
+  // can cross a page: Absolute,X, Absolute,Y and (Indirect),Y
+  fn page_index(&self, opcode: u8) -> Option<u8>;
+
+  // A pointer stored in the zero page, whose high byte wraps around to $00
+  // rather than carrying into the stack page
+  fn read_pointer(&self, pointer: u8) -> Result<u16, CpuError>;
 }
 
 impl Fetch for System {
-  fn fetch(&mut self) -> u8 {
-    let result = self.read(self.registers.pc.address());
+  fn fetch(&mut self) -> Result<u8, CpuError> {
+    let result = self.try_read(self.registers.pc.address())?;
     self.registers.pc.increment();
-    result
+    Ok(result)
   }
 
-  fn fetch_word(&mut self) -> u16 {
-    let lo = self.fetch();
-    let hi = self.fetch();
-    (hi as u16) << 8 | lo as u16
+  fn fetch_word(&mut self) -> Result<u16, CpuError> {
+    let lo = self.fetch()?;
+    let hi = self.fetch()?;
+    Ok((hi as u16) << 8 | lo as u16)
   }
 
-  fn fetch_operand_value(&mut self, opcode: u8) -> u8 {
-    match opcode & 0x1F {
-      0x00 | 0x02 | 0x09 | 0x0B => self.fetch(), // Immediate
+  fn fetch_operand_value(&mut self, opcode: u8) -> Result<u8, CpuError> {
+    let value = match opcode & 0x1F {
+      0x00 | 0x02 | 0x09 | 0x0B => self.fetch()?, // Immediate
       0x08 | 0x18 | 0x1A => panic!("Implied operand has no value"),
       0x12 => panic!("Invalid opcode"),
       0x0A => self.registers.a,
       _ => {
-        let address = self.fetch_operand_address(opcode);
-        self.read(address)
+        let address = self.fetch_operand_address(opcode)?;
+
+        // Indexing carries into the high byte a cycle late. Stores and
+        // read-modify-write instructions always take that cycle, so their
+        // base counts include it.
+        if let Some(index) = self.page_index(opcode) {
+          let base = address.wrapping_sub(index as u16);
+          let unfixed = (base & 0xFF00) | (address & 0x00FF);
+          if unfixed != address {
+            self.cross_page(unfixed);
+          }
+        }
+
+        self.try_read(address)?
       }
-    }
+    };
+
+    self.note_transfer(value);
+    Ok(value)
   }
 
-  fn fetch_operand_address(&mut self, opcode: u8) -> u16 {
-    match opcode & 0x1F {
+  fn fetch_operand_address(&mut self, opcode: u8) -> Result<u16, CpuError> {
+    let address = match opcode & 0x1F {
       0x00 | 0x02 | 0x09 | 0x0B => panic!("Immediate operand has no address"),
       0x01 | 0x03 => {
         // (Indirect,X)
-        let base = self.fetch();
-        let pointer = (base + self.registers.x) as u16;
-        self.read_word(pointer)
+        let base = self.fetch()?;
+        self.read_pointer(base.wrapping_add(self.registers.x))
       }
-      0x04 | 0x05 | 0x06 | 0x07 => self.fetch() as u16, // Zero page
+      0x04..=0x07 => Ok(self.fetch()? as u16), // Zero page
       0x08 | 0x0A | 0x18 | 0x1A => panic!("Implied operand has no address"),
-      0x0C | 0x0D | 0x0E | 0x0F => self.fetch_word(), // Absolute
-      0x10 => self.fetch() as i8 as u16,              // Relative
+      0x0C..=0x0F => self.fetch_word(),       // Absolute
+      0x10 => Ok(self.fetch()? as i8 as u16), // Relative
       0x11 | 0x13 => {
         // (Indirect),Y
-        let base = self.fetch();
-        let pointer = self.read_word(base as u16);
-        pointer + self.registers.y as u16
+        let base = self.fetch()?;
+        let pointer = self.read_pointer(base)?;
+        Ok(pointer.wrapping_add(self.registers.y as u16))
       }
       0x12 => panic!("Invalid opcode"),
       0x14 | 0x15 => {
         // Zero page,X
-        let base = self.fetch();
-        (base + self.registers.x) as u16
+        let base = self.fetch()?;
+        Ok(base.wrapping_add(self.registers.x) as u16)
       }
       0x16 | 0x17 => {
         // Zero page,X or Zero page,Y
-        let base = self.fetch();
+        let base = self.fetch()?;
         if opcode & 0xC0 == 0x80 {
-          (base + self.registers.y) as u16
+          Ok(base.wrapping_add(self.registers.y) as u16)
         } else {
-          (base + self.registers.x) as u16
+          Ok(base.wrapping_add(self.registers.x) as u16)
         }
       }
       0x19 | 0x1B => {
         // Absolute,Y
-        let base = self.fetch_word();
-        base + self.registers.y as u16
+        let base = self.fetch_word()?;
+        Ok(base.wrapping_add(self.registers.y as u16))
       }
       0x1C | 0x1D => {
         // Absolute,X
-        let base = self.fetch_word();
-        base + self.registers.x as u16
+        let base = self.fetch_word()?;
+        Ok(base.wrapping_add(self.registers.x as u16))
       }
       0x1E | 0x1F => {
         // Absolute,X or Absolute,Y
-        let base = self.fetch_word();
+        let base = self.fetch_word()?;
         if opcode & 0xC0 == 0x80 {
-          base + self.registers.y as u16
+          Ok(base.wrapping_add(self.registers.y as u16))
         } else {
-          base + self.registers.x as u16
+          Ok(base.wrapping_add(self.registers.x as u16))
         }
       }
       _ => unreachable!(),
+    }?;
+
+    self.note_address(address);
+    Ok(address)
+  }
+
+  fn page_index(&self, opcode: u8) -> Option<u8> {
+    match opcode & 0x1F {
+      0x11 | 0x13 | 0x19 | 0x1B => Some(self.registers.y),
+      0x1C | 0x1D => Some(self.registers.x),
+      0x1E | 0x1F if opcode & 0xC0 == 0x80 => Some(self.registers.y),
+      0x1E | 0x1F => Some(self.registers.x),
+      _ => None,
     }
   }
+
+  fn fetch_operand_rmw(&mut self, opcode: u8) -> Result<(u16, u8), CpuError> {
+    let address = self.fetch_operand_address(opcode)?;
+    let value = self.try_read(address)?;
+    self.note_transfer(value);
+    Ok((address, value))
+  }
+
+  fn read_pointer(&self, pointer: u8) -> Result<u16, CpuError> {
+    let lo = self.try_read(pointer as u16)?;
+    let hi = self.try_read(pointer.wrapping_add(1) as u16)?;
+    Ok((hi as u16) << 8 | lo as u16)
+  }
 }
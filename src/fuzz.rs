@@ -0,0 +1,43 @@
+use crate::memory::{BlockMemory, Memory};
+use crate::system::System;
+
+// Entry point for fuzzing the CPU: runs arbitrary bytes as a program from the
+// bottom of a flat 64K of memory. Execution may fail, but must never panic.
+pub fn run(data: &[u8], max_instructions: u64) {
+  let mut memory = BlockMemory::rom(0x10000);
+  for (address, &value) in data.iter().take(0xFFFA).enumerate() {
+    memory.write(address as u16, value);
+  }
+
+  // Start at 0x0000, and send interrupts there too
+  for vector in 0xFFFA..=0xFFFF {
+    memory.write(vector, 0x00);
+  }
+
+  let mut system = System::new(Box::new(memory));
+  system.reset();
+
+  for _ in 0..max_instructions {
+    if system.step().is_err() {
+      return;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::rngs::StdRng;
+  use rand::{Rng, SeedableRng};
+
+  #[test]
+  fn random_programs_never_panic() {
+    let mut rng = StdRng::seed_from_u64(6502);
+
+    for _ in 0..1000 {
+      let mut data = vec![0; rng.gen_range(1..=512)];
+      rng.fill(&mut data[..]);
+      run(&data, 1000);
+    }
+  }
+}
@@ -0,0 +1,50 @@
+use crate::graphics::{Capabilities, Color, GraphicsProvider, Image};
+
+// Renders into an in-memory image without ever opening a window
+pub struct HeadlessGraphicsProvider {
+  image: Image,
+}
+
+impl HeadlessGraphicsProvider {
+  pub fn new() -> Self {
+    Self {
+      image: Image::new(0, 0),
+    }
+  }
+}
+
+impl GraphicsProvider for HeadlessGraphicsProvider {
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      window: false,
+      title: false,
+      keyboard: false,
+      multiple_windows: true,
+      present_every_tick: false,
+    }
+  }
+
+  fn create_window(&mut self, width: u32, height: u32, _scale: f64) {
+    self.image = Image::new(width, height);
+  }
+
+  fn set_title(&mut self, _title: &str) {}
+
+  fn tick(&mut self) {}
+
+  fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+    if (x >= self.image.width) || (y >= self.image.height) {
+      println!(
+        "Invalid pixel coordinates ({}, {}) for dimensions ({}, {})",
+        x, y, self.image.width, self.image.height
+      );
+      return;
+    }
+
+    self.image.set_pixel(x, y, color);
+  }
+
+  fn image(&self) -> &Image {
+    &self.image
+  }
+}
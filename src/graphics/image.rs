@@ -0,0 +1,44 @@
+use crate::graphics::Color;
+
+// A framebuffer, with each pixel stored as 0x00RRGGBB
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+  pub width: u32,
+  pub height: u32,
+  pub pixels: Vec<u32>,
+}
+
+impl Image {
+  pub fn new(width: u32, height: u32) -> Self {
+    Self {
+      width,
+      height,
+      pixels: vec![0; (width * height) as usize],
+    }
+  }
+
+  pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+    Color::from_u32(self.pixels[(y * self.width + x) as usize])
+  }
+
+  pub fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
+    self.pixels[(y * self.width + x) as usize] = color.to_u32();
+  }
+
+  pub fn rows(&self) -> impl Iterator<Item = &[u32]> {
+    self.pixels.chunks(self.width.max(1) as usize)
+  }
+
+  pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image {
+    let mut cropped = Image::new(width, height);
+
+    for row in 0..height {
+      let start = ((y + row) * self.width + x) as usize;
+      let dest = (row * width) as usize;
+      cropped.pixels[dest..(dest + width as usize)]
+        .copy_from_slice(&self.pixels[start..(start + width as usize)]);
+    }
+
+    cropped
+  }
+}
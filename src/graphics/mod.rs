@@ -1,6 +1,12 @@
+mod headless;
+mod image;
+mod overlay;
 mod winit;
 
-pub use self::winit::WinitGraphicsProvider;
+pub use self::headless::HeadlessGraphicsProvider;
+pub use self::image::Image;
+pub use self::overlay::MemoryOverlay;
+pub use self::winit::{WinitDriver, WinitGraphicsProvider};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Color {
@@ -14,14 +20,35 @@ impl Color {
     Self { r, g, b }
   }
 
-  pub fn to_rgba(&self) -> [u8; 4] {
+  pub fn to_rgba(self) -> [u8; 4] {
     [self.r, self.g, self.b, 255]
   }
+
+  pub fn to_u32(self) -> u32 {
+    (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+  }
+
+  pub fn from_u32(value: u32) -> Self {
+    Self::new((value >> 16) as u8, (value >> 8) as u8, value as u8)
+  }
+}
+
+// What a graphics provider supports, so callers can adapt to the backend
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Capabilities {
+  pub window: bool,             // shows its output on screen
+  pub title: bool,              // set_title has a visible effect
+  pub keyboard: bool,           // produces key events (see WinitGraphicsProvider::input)
+  pub multiple_windows: bool,   // create_window can be called more than once
+  pub present_every_tick: bool, // needs tick on every frame to stay responsive
 }
 
 pub trait GraphicsProvider {
+  fn capabilities(&self) -> Capabilities;
+
   fn create_window(&mut self, width: u32, height: u32, scale: f64);
+  fn set_title(&mut self, title: &str);
   fn tick(&mut self);
   fn set_pixel(&mut self, x: u32, y: u32, color: Color);
-  fn get_last_key(&self) -> u8;
+  fn image(&self) -> &Image;
 }
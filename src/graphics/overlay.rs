@@ -0,0 +1,273 @@
+use crate::graphics::{Color, GraphicsProvider, Image};
+use crate::input::{InputProvider, KeyEvent, QueueInputProvider};
+use crate::system::System;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+const TOGGLE: u8 = b'\t';
+
+const BACKGROUND: Color = Color { r: 0, g: 0, b: 0 };
+const FOREGROUND: Color = Color {
+  r: 255,
+  g: 255,
+  b: 255,
+};
+
+// Each character is a 3x5 glyph in a 4x6 cell
+const CHAR_WIDTH: u32 = 4;
+const CHAR_HEIGHT: u32 = 6;
+
+#[rustfmt::skip]
+const DIGITS: [[u8; 5]; 16] = [
+  [0b111, 0b101, 0b101, 0b101, 0b111], // 0
+  [0b010, 0b110, 0b010, 0b010, 0b111], // 1
+  [0b111, 0b001, 0b111, 0b100, 0b111], // 2
+  [0b111, 0b001, 0b111, 0b001, 0b111], // 3
+  [0b101, 0b101, 0b111, 0b001, 0b001], // 4
+  [0b111, 0b100, 0b111, 0b001, 0b111], // 5
+  [0b111, 0b100, 0b111, 0b101, 0b111], // 6
+  [0b111, 0b001, 0b001, 0b001, 0b001], // 7
+  [0b111, 0b101, 0b111, 0b101, 0b111], // 8
+  [0b111, 0b101, 0b111, 0b001, 0b111], // 9
+  [0b111, 0b101, 0b111, 0b101, 0b101], // A
+  [0b110, 0b101, 0b110, 0b101, 0b110], // B
+  [0b111, 0b100, 0b100, 0b100, 0b111], // C
+  [0b110, 0b101, 0b101, 0b101, 0b110], // D
+  [0b111, 0b100, 0b111, 0b100, 0b111], // E
+  [0b111, 0b100, 0b111, 0b100, 0b100], // F
+];
+
+// A hex editor drawn over the screen, opened and closed with tab. While it's
+// open the CPU is paused and keys go to the editor rather than the devices:
+// hex digits overwrite the byte under the cursor, high nibble first, and
+// i/j/k/l move the cursor up a row, left, down a row and right. Keys are
+// passed through while it's closed, so it sits between the live input and
+// the event queue (see input).
+pub struct MemoryOverlay {
+  source: Box<dyn InputProvider>,
+  keys: Rc<RefCell<VecDeque<KeyEvent>>>,
+  behind: Option<Image>, // the screen under the editor, while it's open
+  cursor: u16,
+  high_nibble: Option<u8>, // typed, waiting for the low one
+}
+
+impl MemoryOverlay {
+  pub fn new(source: Box<dyn InputProvider>) -> Self {
+    Self {
+      source,
+      keys: Rc::new(RefCell::new(VecDeque::new())),
+      behind: None,
+      cursor: 0,
+      high_nibble: None,
+    }
+  }
+
+  // The keys that weren't meant for the editor
+  pub fn input(&self) -> QueueInputProvider {
+    QueueInputProvider::new(Rc::clone(&self.keys))
+  }
+
+  // Handle the keys pressed since the last frame, drawing the editor if it's
+  // open. Returns whether it is, in which case the CPU shouldn't run.
+  pub fn update(
+    &mut self,
+    system: &mut System,
+    graphics: &RefCell<Box<dyn GraphicsProvider>>,
+  ) -> bool {
+    self.source.tick();
+    while let Some(key) = self.source.poll() {
+      match (key, self.behind.is_some()) {
+        (KeyEvent::Down(TOGGLE), true) => self.close(graphics.borrow_mut().as_mut()),
+        (KeyEvent::Down(TOGGLE), false) => self.open(system, graphics.borrow().as_ref()),
+        (KeyEvent::Down(key), true) => self.key(system, graphics, key),
+        // Releases always go through, so no key is left held down
+        _ => self.keys.borrow_mut().push_back(key),
+      }
+    }
+
+    if self.behind.is_none() {
+      return false;
+    }
+
+    let mut graphics = graphics.borrow_mut();
+    self.draw(system, graphics.as_mut());
+    graphics.tick();
+    true
+  }
+
+  fn open(&mut self, system: &System, graphics: &dyn GraphicsProvider) {
+    // Without a window big enough for a row, there's nowhere to draw it
+    if columns(graphics.image()) == 0 {
+      return;
+    }
+
+    self.behind = Some(graphics.image().clone());
+    self.cursor = system.registers.pc.address();
+    self.high_nibble = None;
+  }
+
+  fn close(&mut self, graphics: &mut dyn GraphicsProvider) {
+    if let Some(behind) = self.behind.take() {
+      for y in 0..behind.height {
+        for x in 0..behind.width {
+          graphics.set_pixel(x, y, behind.get_pixel(x, y));
+        }
+      }
+    }
+  }
+
+  fn key(&mut self, system: &mut System, graphics: &RefCell<Box<dyn GraphicsProvider>>, key: u8) {
+    let row = columns(graphics.borrow().image());
+
+    let digit = match key {
+      b'i' => return self.step(-(row as i32)),
+      b'k' => return self.step(row as i32),
+      b'j' => return self.step(-1),
+      b'l' => return self.step(1),
+      _ => match (key as char).to_digit(16) {
+        Some(digit) => digit as u8,
+        None => return,
+      },
+    };
+
+    let high = match self.high_nibble.take() {
+      Some(high) => high,
+      None => {
+        self.high_nibble = Some(digit);
+        return;
+      }
+    };
+
+    // The write can draw to the screen, like a character into PET video
+    // memory, which belongs under the editor rather than on top of it. The
+    // device borrows the graphics to do that, so they can't be held here.
+    let before = graphics.borrow().image().clone();
+    system.patch(self.cursor, &[high << 4 | digit]);
+
+    if let Some(behind) = &mut self.behind {
+      let graphics = graphics.borrow();
+      let after = graphics.image();
+      for (index, (&old, &new)) in before.pixels.iter().zip(&after.pixels).enumerate() {
+        if old != new {
+          behind.pixels[index] = new;
+        }
+      }
+    }
+
+    self.step(1);
+  }
+
+  fn step(&mut self, offset: i32) {
+    self.cursor = self.cursor.wrapping_add(offset as u16);
+    self.high_nibble = None;
+  }
+
+  // Rows of bytes with their addresses, centred on the row with the cursor,
+  // which is shown inverted
+  fn draw(&self, system: &System, graphics: &mut dyn GraphicsProvider) {
+    let image = graphics.image();
+    let (width, height) = (image.width, image.height);
+    let row = columns(image);
+    let rows = height / CHAR_HEIGHT;
+
+    for y in 0..height {
+      for x in 0..width {
+        graphics.set_pixel(x, y, BACKGROUND);
+      }
+    }
+
+    let cursor_row = self.cursor / row as u16;
+    let first = cursor_row.wrapping_sub(rows as u16 / 2);
+
+    for line in 0..rows {
+      let address = first.wrapping_add(line as u16).wrapping_mul(row as u16);
+      let y = line * CHAR_HEIGHT;
+
+      for (index, shift) in [12, 8, 4, 0].into_iter().enumerate() {
+        let digit = (address >> shift) as u8 & 0xF;
+        draw_digit(graphics, index as u32 * CHAR_WIDTH, y, digit, false);
+      }
+
+      for column in 0..row {
+        let byte_address = address.wrapping_add(column as u16);
+        let selected = byte_address == self.cursor;
+        let value = match (selected, self.high_nibble) {
+          (true, Some(high)) => high << 4 | system.peek(byte_address) & 0xF,
+          _ => system.peek(byte_address),
+        };
+
+        let x = (5 + column * 3) * CHAR_WIDTH;
+        draw_digit(graphics, x, y, value >> 4, selected);
+        draw_digit(graphics, x + CHAR_WIDTH, y, value & 0xF, selected);
+      }
+    }
+  }
+}
+
+// How many bytes fit on a row after the address, rounded down to a power of
+// two so rows start at round addresses
+fn columns(image: &Image) -> u32 {
+  if image.height < CHAR_HEIGHT {
+    return 0;
+  }
+
+  let fit = (image.width / CHAR_WIDTH).saturating_sub(4) / 3;
+  [16, 8, 4, 2, 1]
+    .into_iter()
+    .find(|&columns| columns <= fit)
+    .unwrap_or(0)
+}
+
+// The cell includes the gap below and to the right of the glyph, so
+// inverted digits next to each other join up
+fn draw_digit(graphics: &mut dyn GraphicsProvider, x: u32, y: u32, digit: u8, inverted: bool) {
+  for row in 0..CHAR_HEIGHT {
+    for column in 0..CHAR_WIDTH {
+      let lit =
+        row < 5 && column < 3 && DIGITS[digit as usize][row as usize] & (0b100 >> column) != 0;
+      let color = if lit != inverted {
+        FOREGROUND
+      } else {
+        BACKGROUND
+      };
+      graphics.set_pixel(x + column, y + row, color);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::graphics::HeadlessGraphicsProvider;
+  use crate::memory::easy::EasyVram;
+  use crate::memory::{BlockMemory, BranchMemory};
+
+  #[test]
+  fn edits_to_video_memory_are_drawn_under_the_editor() {
+    let graphics: Rc<RefCell<Box<dyn GraphicsProvider>>> =
+      Rc::new(RefCell::new(Box::new(HeadlessGraphicsProvider::new())));
+    let memory = BranchMemory::new()
+      .map(0x0000, Box::new(BlockMemory::ram(0x0200)))
+      .map(
+        0x0200,
+        Box::new(EasyVram::new(32, 32, Rc::clone(&graphics))),
+      )
+      .map(0x0600, Box::new(BlockMemory::ram(0xFA00)));
+    let mut system = System::new(Box::new(memory));
+    system.registers.pc.load(0x0200);
+
+    let keys = Rc::new(RefCell::new(VecDeque::new()));
+    let mut overlay = MemoryOverlay::new(Box::new(QueueInputProvider::new(Rc::clone(&keys))));
+
+    // Open the editor at the PC, write 05 over the first pixel and close it
+    keys
+      .borrow_mut()
+      .extend([TOGGLE, b'0', b'5', TOGGLE].into_iter().map(KeyEvent::Down));
+    assert!(!overlay.update(&mut system, &graphics));
+
+    assert_eq!(system.peek(0x0200), 0x05);
+    let green = Color::from_u32(0x00CC55);
+    assert_eq!(graphics.borrow().image().get_pixel(0, 0), green);
+  }
+}
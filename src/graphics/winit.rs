@@ -1,48 +1,81 @@
-use crate::graphics::{Color, GraphicsProvider};
+use crate::graphics::{Capabilities, Color, GraphicsProvider, Image};
+use crate::input::{KeyEvent, QueueInputProvider};
 use pixels::{Pixels, SurfaceTexture};
-use winit::dpi::LogicalSize;
-use winit::event::{Event, VirtualKeyCode, WindowEvent};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::platform::run_return::EventLoopExtRunReturn;
 use winit::window::{Window, WindowBuilder};
-use winit_input_helper::WinitInputHelper;
 
+const FRAME_TIME: Duration = Duration::from_micros(16_667);
+
+// The event loop has to own the main thread, so the driver runs the CPU from
+// inside it (see WinitDriver::run). Windows must be created before then.
 pub struct WinitGraphicsProvider {
-  event_loop: EventLoop<()>,
-  input: WinitInputHelper,
+  event_loop: Rc<RefCell<Option<EventLoop<()>>>>,
   window: Option<Window>,
   pixels: Option<Pixels>,
-  dimensions: Option<(u32, u32)>,
-  last_key: u8,
+  image: Image,
   dirty: bool,
+  keys: Rc<RefCell<VecDeque<KeyEvent>>>,
+  resized: Rc<Cell<Option<PhysicalSize<u32>>>>,
 }
 
 impl WinitGraphicsProvider {
   pub fn new() -> Self {
-    let event_loop = EventLoop::new();
-    let input = WinitInputHelper::new();
-
     Self {
-      event_loop,
-      input,
+      event_loop: Rc::new(RefCell::new(Some(EventLoop::new()))),
       window: None,
       pixels: None,
-      dimensions: None,
-      last_key: 0,
+      image: Image::new(0, 0),
       dirty: true,
+      keys: Rc::new(RefCell::new(VecDeque::new())),
+      resized: Rc::new(Cell::new(None)),
+    }
+  }
+
+  pub fn input(&self) -> QueueInputProvider {
+    QueueInputProvider::new(Rc::clone(&self.keys))
+  }
+
+  pub fn driver(&self) -> WinitDriver {
+    WinitDriver {
+      event_loop: Rc::clone(&self.event_loop),
+      keys: Rc::clone(&self.keys),
+      resized: Rc::clone(&self.resized),
+      held: HashMap::new(),
+      last_scancode: None,
     }
   }
 }
 
 impl GraphicsProvider for WinitGraphicsProvider {
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      window: true,
+      title: true,
+      keyboard: true,
+      multiple_windows: false,
+      present_every_tick: false,
+    }
+  }
+
   fn create_window(&mut self, width: u32, height: u32, scale: f64) {
+    let event_loop = self.event_loop.borrow();
+    let event_loop = event_loop
+      .as_ref()
+      .expect("Windows must be created before the event loop starts");
+
     let window = WindowBuilder::new()
       .with_title("noentiendo")
       .with_inner_size(LogicalSize::new(
         width as f64 * scale,
         height as f64 * scale,
       ))
-      .build(&self.event_loop)
+      .build(event_loop)
       .unwrap();
 
     let inner_size = window.inner_size();
@@ -51,58 +84,123 @@ impl GraphicsProvider for WinitGraphicsProvider {
 
     self.window = Some(window);
     self.pixels = Some(pixels);
-    self.dimensions = Some((width, height));
+    self.image = Image::new(width, height);
+  }
+
+  // There's nothing to name until the window is created
+  fn set_title(&mut self, title: &str) {
+    if let Some(window) = &self.window {
+      window.set_title(title);
+    }
   }
 
   fn tick(&mut self) {
     let pixels = self.pixels.as_mut().unwrap();
 
-    self.event_loop.run_return(|event, _, control_flow| {
-      if self.input.update(&event) {
-        if self.input.key_pressed(VirtualKeyCode::Escape) || self.input.quit() {
-          panic!("Quit");
-        }
+    if let Some(size) = self.resized.take() {
+      pixels.resize_surface(size.width, size.height);
+      self.dirty = true;
+    }
 
-        if let Some(size) = self.input.window_resized() {
-          pixels.resize_surface(size.width, size.height);
-        }
-      }
+    if self.dirty {
+      self.dirty = false;
 
-      if let Event::WindowEvent { event, .. } = event {
-        if let WindowEvent::ReceivedCharacter(c) = event {
-          self.last_key = c as u8;
-        }
+      let frame = pixels.get_frame();
+      for (pixel, &value) in frame.chunks_exact_mut(4).zip(&self.image.pixels) {
+        pixel.copy_from_slice(&Color::from_u32(value).to_rgba());
       }
 
-      *control_flow = ControlFlow::Exit;
-    });
-
-    if self.dirty {
-      self.dirty = false;
       pixels.render().unwrap();
     }
   }
 
   fn set_pixel(&mut self, x: u32, y: u32, color: Color) {
-    let frame = self.pixels.as_mut().unwrap().get_frame();
-    let (width, height) = self.dimensions.unwrap();
-
-    if (x >= width) || (y >= height) {
+    if (x >= self.image.width) || (y >= self.image.height) {
       println!(
         "Invalid pixel coordinates ({}, {}) for dimensions ({}, {})",
-        x, y, width, height
+        x, y, self.image.width, self.image.height
       );
       return;
     }
 
-    let index = ((y * width + x) * 4) as usize;
-    let pixel = &mut frame[index..(index + 4)];
-    pixel.copy_from_slice(&color.to_rgba());
-
+    self.image.set_pixel(x, y, color);
     self.dirty = true;
   }
 
-  fn get_last_key(&self) -> u8 {
-    self.last_key
+  fn image(&self) -> &Image {
+    &self.image
+  }
+}
+
+// Runs the winit event loop, handing key presses and resizes over to the
+// graphics provider
+pub struct WinitDriver {
+  event_loop: Rc<RefCell<Option<EventLoop<()>>>>,
+  keys: Rc<RefCell<VecDeque<KeyEvent>>>,
+  resized: Rc<Cell<Option<PhysicalSize<u32>>>>,
+  held: HashMap<u32, u8>,     // scancode -> character
+  last_scancode: Option<u32>, // most recently pressed, awaiting its character
+}
+
+impl WinitDriver {
+  // Call the given function once per frame, at 60 frames per second, until
+  // the window is closed or escape is pressed
+  pub fn run(mut self, mut frame: impl FnMut() + 'static) -> ! {
+    let event_loop = self
+      .event_loop
+      .borrow_mut()
+      .take()
+      .expect("The event loop is already running");
+    let mut next_frame = Instant::now();
+
+    event_loop.run(move |event, _, control_flow| match event {
+      Event::WindowEvent { event, .. } => self.window_event(event, control_flow),
+      Event::MainEventsCleared if *control_flow != ControlFlow::Exit => {
+        if Instant::now() >= next_frame {
+          frame();
+          next_frame += FRAME_TIME;
+
+          // Don't try to catch up after falling behind
+          next_frame = next_frame.max(Instant::now());
+        }
+
+        *control_flow = ControlFlow::WaitUntil(next_frame);
+      }
+      _ => {}
+    })
+  }
+
+  fn window_event(&mut self, event: WindowEvent, control_flow: &mut ControlFlow) {
+    match event {
+      WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+      WindowEvent::Resized(size) => self.resized.set(Some(size)),
+      WindowEvent::KeyboardInput {
+        input:
+          KeyboardInput {
+            scancode,
+            state,
+            virtual_keycode,
+            ..
+          },
+        ..
+      } => match state {
+        ElementState::Pressed if virtual_keycode == Some(VirtualKeyCode::Escape) => {
+          *control_flow = ControlFlow::Exit
+        }
+        ElementState::Pressed => self.last_scancode = Some(scancode),
+        ElementState::Released => {
+          if let Some(c) = self.held.remove(&scancode) {
+            self.keys.borrow_mut().push_back(KeyEvent::Up(c));
+          }
+        }
+      },
+      WindowEvent::ReceivedCharacter(c) => {
+        if let Some(scancode) = self.last_scancode.take() {
+          self.held.insert(scancode, c as u8);
+        }
+        self.keys.borrow_mut().push_back(KeyEvent::Down(c as u8));
+      }
+      _ => {}
+    }
   }
 }
@@ -0,0 +1,37 @@
+use crate::input::{InputProvider, KeyEvent};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Analog inputs set by the host, from a mouse position or gamepad axis. It
+// has no key events of its own. The host calls set_axis on a clone of the
+// provider it gave the device, since they all read the same values.
+#[derive(Clone)]
+pub struct AnalogInputProvider {
+  axes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl AnalogInputProvider {
+  pub fn new(axes: usize) -> Self {
+    Self {
+      axes: Rc::new(RefCell::new(vec![0x80; axes])),
+    }
+  }
+
+  pub fn set_axis(&self, index: usize, value: u8) {
+    if let Some(axis) = self.axes.borrow_mut().get_mut(index) {
+      *axis = value;
+    }
+  }
+}
+
+impl InputProvider for AnalogInputProvider {
+  fn tick(&mut self) {}
+
+  fn poll(&mut self) -> Option<KeyEvent> {
+    None
+  }
+
+  fn axis(&self, index: usize) -> Option<u8> {
+    self.axes.borrow().get(index).copied()
+  }
+}
@@ -0,0 +1,24 @@
+mod analog;
+mod queue;
+mod scripted;
+
+pub use self::analog::AnalogInputProvider;
+pub use self::queue::QueueInputProvider;
+pub use self::scripted::ScriptedInputProvider;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyEvent {
+  Down(u8),
+  Up(u8),
+}
+
+pub trait InputProvider {
+  fn tick(&mut self);
+  fn poll(&mut self) -> Option<KeyEvent>;
+
+  // The current position of an analog input, like a paddle or one coordinate
+  // of a light pen, from 0 to 255, or None if the provider doesn't have it
+  fn axis(&self, _index: usize) -> Option<u8> {
+    None
+  }
+}
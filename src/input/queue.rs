@@ -0,0 +1,24 @@
+use crate::input::{InputProvider, KeyEvent};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+// Key events pushed onto a shared queue from elsewhere, such as the winit
+// event loop owned by the graphics provider (see WinitGraphicsProvider::input)
+pub struct QueueInputProvider {
+  events: Rc<RefCell<VecDeque<KeyEvent>>>,
+}
+
+impl QueueInputProvider {
+  pub fn new(events: Rc<RefCell<VecDeque<KeyEvent>>>) -> Self {
+    Self { events }
+  }
+}
+
+impl InputProvider for QueueInputProvider {
+  fn tick(&mut self) {}
+
+  fn poll(&mut self) -> Option<KeyEvent> {
+    self.events.borrow_mut().pop_front()
+  }
+}
@@ -0,0 +1,36 @@
+use crate::input::{InputProvider, KeyEvent};
+use std::collections::VecDeque;
+
+// Replays a fixed list of key events, each delivered once the given number
+// of ticks has elapsed
+pub struct ScriptedInputProvider {
+  events: VecDeque<(u64, KeyEvent)>,
+  ticks: u64,
+}
+
+impl ScriptedInputProvider {
+  pub fn new(mut events: Vec<(u64, KeyEvent)>) -> Self {
+    events.sort_by_key(|&(tick, _)| tick);
+
+    Self {
+      events: events.into(),
+      ticks: 0,
+    }
+  }
+}
+
+impl InputProvider for ScriptedInputProvider {
+  fn tick(&mut self) {
+    self.ticks += 1;
+  }
+
+  fn poll(&mut self) -> Option<KeyEvent> {
+    match self.events.front() {
+      Some(&(tick, event)) if tick <= self.ticks => {
+        self.events.pop_front();
+        Some(event)
+      }
+      _ => None,
+    }
+  }
+}
@@ -0,0 +1,39 @@
+// A 6502 emulator core. System ties the CPU to a Memory map, and the memory
+// module provides the devices and preset systems to build one from.
+
+// Devices are built with new(), without Default
+#![allow(clippy::new_without_default)]
+
+pub mod assembler;
+pub mod audio;
+pub mod builder;
+mod cycles;
+pub mod debugger;
+pub mod disassembler;
+pub mod execute;
+pub mod fetch;
+pub mod fuzz;
+#[cfg(feature = "graphics")]
+pub mod graphics;
+pub mod input;
+pub mod memory;
+pub mod opcodes;
+pub mod profiler;
+pub mod registers;
+pub mod replay;
+pub mod rewind;
+pub mod system;
+pub mod trace;
+mod watchdog;
+
+pub use builder::SystemBuilder;
+pub use execute::Execute;
+pub use fetch::Fetch;
+#[cfg(feature = "graphics")]
+pub use graphics::GraphicsProvider;
+pub use input::InputProvider;
+pub use memory::Memory;
+pub use registers::{ProgramCounter, RegisterSnapshot, Registers, StackPointer, StatusRegister};
+pub use system::{
+  Access, CpuError, CpuVariant, CycleDetail, SaveState, StackError, System, Vectors,
+};
@@ -1,48 +1,383 @@
-mod execute;
-mod fetch;
-mod graphics;
-mod memory;
-mod registers;
-mod system;
-
 use clap::Parser;
+#[cfg(feature = "graphics")]
+use noentiendo::graphics;
+use noentiendo::{debugger, input, memory, replay, system, trace, SystemBuilder};
+#[cfg(feature = "graphics")]
+use std::cell::RefCell;
+#[cfg(feature = "graphics")]
+use std::rc::Rc;
+#[cfg(feature = "watch")]
+use std::time::SystemTime;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
   #[clap(short, long, value_parser)]
-  rom_path: String,
+  rom_path: Option<String>,
+
+  #[clap(long = "rom", value_parser)]
+  roms: Vec<String>,
 
   #[clap(short, long, value_parser)]
   system: String,
 
   #[clap(short, long, value_parser)]
   graphics: String,
+
+  #[clap(long, value_parser)]
+  strict_memory: bool,
+
+  #[clap(long, value_parser)]
+  permissive: bool,
+
+  #[clap(long, value_parser)]
+  stack_errors: bool,
+
+  #[clap(long, value_parser)]
+  watchdog: Option<u64>,
+
+  #[clap(long, value_parser)]
+  debug: bool,
+
+  #[clap(long, value_parser)]
+  max_instructions: Option<u64>,
+
+  #[clap(long, value_parser)]
+  record: Option<String>,
+
+  #[clap(long, value_parser)]
+  replay: Option<String>,
+
+  #[clap(long, value_parser)]
+  reference: Option<String>,
+
+  #[clap(long, value_parser)]
+  trace: bool,
+
+  #[clap(long, value_parser)]
+  listing: Option<String>,
+
+  #[clap(long, value_parser)]
+  listing_cycles: bool,
+
+  #[clap(long, value_parser)]
+  watch: bool,
+
+  #[clap(long, value_parser)]
+  memory_editor: bool,
 }
 
 fn main() {
   let args = Args::parse();
 
-  let graphics: Option<Box<dyn graphics::GraphicsProvider>> = match args.graphics.as_str() {
-    "none" => None,
-    "winit" => Some(Box::new(graphics::WinitGraphicsProvider::new())),
+  #[cfg(feature = "graphics")]
+  let mut graphics: Option<Box<dyn graphics::GraphicsProvider>> = None;
+  #[cfg(feature = "graphics")]
+  let mut driver = None;
+  #[cfg(feature = "graphics")]
+  let mut overlay = None;
+
+  // Live input reaches the devices through the event queue, so it can be
+  // recorded and replayed
+  let mut events = replay::EventQueue::new();
+  let input: Option<Box<dyn input::InputProvider>> = Some(Box::new(events.input()));
+
+  match args.graphics.as_str() {
+    "none" => {}
+    #[cfg(feature = "graphics")]
+    "headless" => {
+      graphics = Some(Box::new(graphics::HeadlessGraphicsProvider::new()));
+    }
+    #[cfg(feature = "graphics")]
+    "winit" => {
+      let winit = graphics::WinitGraphicsProvider::new();
+      driver = Some(winit.driver());
+
+      // The memory editor takes the keys it wants before the devices see them
+      let live: Box<dyn input::InputProvider> = match args.memory_editor {
+        true => {
+          let editor = graphics::MemoryOverlay::new(Box::new(winit.input()));
+          let keys = editor.input();
+          overlay = Some(editor);
+          Box::new(keys)
+        }
+        false => Box::new(winit.input()),
+      };
+
+      // A replay has to see only the recorded keys to run the same way again
+      if args.replay.is_none() {
+        events.listen(live);
+      }
+      graphics = Some(Box::new(winit));
+    }
     _ => panic!("Unknown graphics provider"),
   };
 
+  // The memory editor reads the keys from the window
+  #[cfg(feature = "graphics")]
+  let keyboard = graphics
+    .as_ref()
+    .is_some_and(|graphics| graphics.capabilities().keyboard);
+  #[cfg(not(feature = "graphics"))]
+  let keyboard = false;
+
+  if args.memory_editor && !keyboard {
+    eprintln!("--memory-editor needs a graphics provider with a keyboard, like winit");
+    std::process::exit(1);
+  }
+
+  let recording = args.record.as_ref().map(|path| events.record(path));
+  let replay = args.replay.as_ref().map(|path| events.replay(path));
+
+  for result in [recording, replay].into_iter().flatten() {
+    if let Err(error) = result {
+      eprintln!("{}", error);
+      std::process::exit(1);
+    }
+  }
+
   let mapping = match args.system.as_str() {
-    "brooke" => memory::systems::Mapping::BrookeSystem,
-    "easy" => memory::systems::Mapping::Easy6502,
-    "pet" => memory::systems::Mapping::CommodorePET,
+    "brooke" => Some(memory::systems::Mapping::BrookeSystem),
+    "easy" => Some(memory::systems::Mapping::Easy6502),
+    "pet" => Some(memory::systems::Mapping::CommodorePET),
+    "custom" => None, // mapped from the --rom arguments
     _ => panic!("Unknown system"),
   };
 
-  let memory = memory::systems::create_memory(mapping, graphics, &args.rom_path);
+  // Shared between the devices that draw and the system that presents
+  #[cfg(feature = "graphics")]
+  let graphics = graphics.map(|graphics| Rc::new(RefCell::new(graphics)));
+
+  let memory = match (mapping, &args.rom_path) {
+    (None, _) => custom_memory(&args.roms),
+    (Some(_), None) => Err(format!("The {} system needs a ROM (-r)", args.system)),
+    (Some(_), Some(_)) if !args.roms.is_empty() => {
+      Err("--rom is only for the custom system".to_string())
+    }
+    #[cfg(feature = "graphics")]
+    (Some(mapping), Some(rom_path)) => {
+      memory::systems::create_memory(mapping, graphics.clone(), input, rom_path)
+    }
+    #[cfg(not(feature = "graphics"))]
+    (Some(mapping), Some(rom_path)) => memory::systems::create_memory(mapping, input, rom_path),
+  };
+
+  let memory = match memory {
+    Ok(memory) => memory,
+    Err(error) => {
+      eprintln!("{}", error);
+      std::process::exit(1);
+    }
+  };
+
+  // The devices create the window, if there is one, as they're mapped
+  #[cfg(feature = "graphics")]
+  if let Some(graphics) = &graphics {
+    let mut graphics = graphics.borrow_mut();
+    if graphics.capabilities().title {
+      graphics.set_title(&format!("noentiendo ({})", args.system));
+    }
+  }
 
   let mut system = system::System::new(memory);
+  #[cfg(feature = "graphics")]
+  let overlay_graphics = graphics.clone();
+  #[cfg(feature = "graphics")]
+  if let Some(graphics) = graphics {
+    system.graphics(graphics);
+  }
+  system.event_queue(events);
+  system.strict_memory(args.strict_memory);
+  system.permissive_mode(args.permissive);
+  system.detect_stack_errors(args.stack_errors);
+  system.loop_watchdog(args.watchdog);
 
   system.reset();
 
+  // Check the run against a reference trace log instead
+  if let Some(path) = &args.reference {
+    let log = std::fs::read_to_string(path).unwrap_or_else(|error| {
+      eprintln!("Couldn't read {}: {}", path, error);
+      std::process::exit(1);
+    });
+
+    match trace::compare(&mut system, &log) {
+      Ok(lines) => println!("Matched all {} lines of {}", lines, path),
+      Err(error) => {
+        println!("{}", error);
+        std::process::exit(1);
+      }
+    }
+    return;
+  }
+
+  // Dump the disassembly of the program instead, from its entry point
+  if let Some(path) = &args.listing {
+    let start = system.vectors().reset;
+    if let Err(error) = system.write_listing(path, start, 0xFFFF, args.listing_cycles) {
+      eprintln!("{}", error);
+      std::process::exit(1);
+    }
+    println!("Wrote the listing of {:04X}-FFFF to {}", start, path);
+    return;
+  }
+
+  if args.debug {
+    debugger::Debugger::new().run(&mut system);
+    return;
+  }
+
+  let limit = args.max_instructions;
+  let trace = args.trace;
+  let mut executed = 0;
+
+  #[cfg(not(feature = "watch"))]
+  if args.watch {
+    eprintln!("--watch needs the watch feature");
+    std::process::exit(1);
+  }
+
+  #[cfg(feature = "watch")]
+  let mut watcher = match args.watch {
+    true => Some(RomWatcher::new(rom_paths(&args))),
+    false => None,
+  };
+  #[cfg(feature = "watch")]
+  let mut next_check = 0;
+
+  let mut step = move |system: &mut system::System| {
+    // Look for a rebuilt ROM once a frame
+    #[cfg(feature = "watch")]
+    if let Some(watcher) = &mut watcher {
+      if system.cycles() >= next_check {
+        next_check = system.cycles() + system::CYCLES_PER_FRAME;
+
+        if watcher.changed() {
+          match system.reload() {
+            Ok(()) => println!("Reloaded the ROM"),
+            Err(error) => println!("{}", error),
+          }
+        }
+      }
+    }
+
+    if Some(executed) == limit {
+      println!("Reached the limit of {} instructions", executed);
+      println!("{}", system.registers);
+      println!("Cycles: {}", system.cycles());
+      println!("State hash: {:016X}", system.state_hash());
+      std::process::exit(0);
+    }
+
+    if trace {
+      println!("{}", trace::trace(system));
+    }
+
+    executed += 1;
+    run(system);
+  };
+
+  // Windowed systems run a frame at a time from inside the event loop
+  #[cfg(feature = "graphics")]
+  if let Some(driver) = driver {
+    driver.run(move || {
+      // The CPU is paused while the memory editor is open
+      if let (Some(overlay), Some(graphics)) = (&mut overlay, &overlay_graphics) {
+        if overlay.update(&mut system, graphics) {
+          return;
+        }
+      }
+
+      let end = system.next_frame();
+      while system.cycles() < end {
+        step(&mut system);
+      }
+    });
+  }
+
   loop {
-    system.tick();
+    step(&mut system);
+  }
+}
+
+// The files the ROMs were loaded from
+#[cfg(feature = "watch")]
+fn rom_paths(args: &Args) -> Vec<String> {
+  let roms = args
+    .roms
+    .iter()
+    .filter_map(|rom| rom.split_once('=').map(|(_, path)| path.to_string()));
+  args.rom_path.iter().cloned().chain(roms).collect()
+}
+
+// Notices when any of the files are modified, by polling their timestamps
+#[cfg(feature = "watch")]
+struct RomWatcher {
+  paths: Vec<String>,
+  modified: Vec<Option<SystemTime>>,
+}
+
+#[cfg(feature = "watch")]
+impl RomWatcher {
+  fn new(paths: Vec<String>) -> Self {
+    let modified = paths.iter().map(|path| modified(path)).collect();
+    Self { paths, modified }
+  }
+
+  fn changed(&mut self) -> bool {
+    let modified: Vec<_> = self.paths.iter().map(|path| modified(path)).collect();
+
+    if modified == self.modified {
+      return false;
+    }
+
+    self.modified = modified;
+    true
+  }
+}
+
+#[cfg(feature = "watch")]
+fn modified(path: &str) -> Option<SystemTime> {
+  std::fs::metadata(path)
+    .and_then(|metadata| metadata.modified())
+    .ok()
+}
+
+// The memory map for the custom system: each ROM at its address, given as
+// ADDRESS=PATH with the address in hex, and RAM up to the lowest of them
+fn custom_memory(roms: &[String]) -> Result<Box<dyn memory::Memory>, String> {
+  if roms.is_empty() {
+    return Err("The custom system needs at least one --rom ADDRESS=PATH".to_string());
+  }
+
+  let mut builder = SystemBuilder::new();
+  let mut lowest = 0x10000;
+
+  for rom in roms {
+    let (address, path) = rom
+      .split_once('=')
+      .ok_or_else(|| format!("Expected --rom ADDRESS=PATH, not {}", rom))?;
+    let address = usize::from_str_radix(address.trim_start_matches('$'), 16)
+      .map_err(|_| format!("Invalid ROM address {}", address))?;
+
+    let label = format!("ROM {}", path);
+    builder = builder.rom_file(address, path)?.label(&label);
+    lowest = lowest.min(address);
+  }
+
+  if lowest > 0 {
+    builder = builder.ram(0, lowest).label("RAM");
+  }
+
+  Ok(Box::new(builder.build_memory()?))
+}
+
+// Execute one instruction, stopping the emulator if the CPU halts
+fn run(system: &mut system::System) {
+  if let Err(error) = system.try_tick() {
+    println!("{}", error);
+    println!("Halted at {:04X}", system.last_pc().unwrap_or(0));
+    println!("{}", system.registers);
+    std::process::exit(1);
   }
 }
@@ -0,0 +1,47 @@
+use crate::input::InputProvider;
+use crate::memory::Memory;
+
+// Paddles, a light pen or other analog inputs, one read-only register per
+// axis holding its position from 0 to 255, mirrored through the rest of the
+// device. The values are latched from the input provider on every tick, so a
+// program sees each one change between instructions rather than mid-read.
+// Axes the provider doesn't have read as 0x80, the middle of their range.
+pub struct AnalogInputMemory {
+  input: Box<dyn InputProvider>,
+  values: Vec<u8>,
+}
+
+impl AnalogInputMemory {
+  pub fn new(input: Box<dyn InputProvider>, axes: usize) -> Self {
+    let mut memory = Self {
+      input,
+      values: vec![0x80; axes.max(1)],
+    };
+
+    memory.latch();
+    memory
+  }
+
+  fn latch(&mut self) {
+    for (index, value) in self.values.iter_mut().enumerate() {
+      *value = self.input.axis(index).unwrap_or(0x80);
+    }
+  }
+}
+
+impl Memory for AnalogInputMemory {
+  fn read(&self, address: u16) -> u8 {
+    self.values[address as usize % self.values.len()]
+  }
+
+  fn write(&mut self, _address: u16, _value: u8) {}
+
+  fn tick(&mut self) {
+    self.input.tick();
+    self.latch();
+  }
+
+  fn reset(&mut self) {
+    self.latch();
+  }
+}
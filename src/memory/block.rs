@@ -1,20 +1,49 @@
-use crate::memory::Memory;
+use crate::memory::{read_hex, Memory};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
 use std::fs::File;
 use std::io::Read;
 
+// What RAM contains after a reset. Real hardware powers up with unpredictable
+// contents, so filling with a pattern helps catch reads of uninitialized memory.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FillPolicy {
+  Zero,
+  Byte(u8),
+  Random(u64), // seeded, so every reset gives the same pattern
+}
+
+// Where a ROM's contents were loaded from, so they can be reloaded
+#[derive(Clone, Debug)]
+enum Source {
+  File(String),
+  Hex(usize, String), // mapped at the address
+}
+
 pub struct BlockMemory {
   size: usize,
   data: Vec<u8>,
   persistent: bool,
+  fill: FillPolicy,
+  source: Option<Source>,
 }
 
 impl BlockMemory {
   pub fn ram(size: usize) -> Self {
-    Self {
+    Self::ram_with_fill(size, FillPolicy::Zero)
+  }
+
+  pub fn ram_with_fill(size: usize, fill: FillPolicy) -> Self {
+    let mut memory = Self {
       size,
       data: vec![0; size],
       persistent: false,
-    }
+      fill,
+      source: None,
+    };
+
+    memory.reset();
+    memory
   }
 
   pub fn rom(size: usize) -> Self {
@@ -22,22 +51,75 @@ impl BlockMemory {
       size,
       data: vec![0; size],
       persistent: true,
+      fill: FillPolicy::Zero,
+      source: None,
     }
   }
 
-  pub fn from_file(size: usize, path: &str) -> Self {
-    let mut file = File::open(path).unwrap();
-    let mut data = Vec::new();
-    file.read_to_end(&mut data).unwrap();
-
+  // A ROM holding exactly the given bytes
+  pub fn from_bytes(data: &[u8]) -> Self {
     Self {
+      size: data.len(),
+      data: data.to_vec(),
+      persistent: true,
+      fill: FillPolicy::Zero,
+      source: None,
+    }
+  }
+
+  pub fn from_file(size: usize, path: &str) -> Result<Self, String> {
+    let mut data = read_file(path)?;
+
+    if data.len() > size {
+      return Err(format!(
+        "{} is {} bytes, which doesn't fit in the {} bytes mapped for it",
+        path,
+        data.len(),
+        size
+      ));
+    }
+
+    data.resize(size, 0);
+
+    Ok(Self {
       size,
       data,
       persistent: true,
+      fill: FillPolicy::Zero,
+      source: Some(Source::File(path.to_string())),
+    })
+  }
+}
+
+impl BlockMemory {
+  // The part of a hex image that falls within a block mapped at the address
+  pub fn from_hex(size: usize, address: usize, path: &str) -> Result<Self, String> {
+    let image = read_hex(path)?;
+
+    if address + size > image.data.len() {
+      return Err(format!("{} can't be mapped at {:04X}", path, address));
     }
+
+    Ok(Self {
+      size,
+      data: image.data[address..address + size].to_vec(),
+      persistent: true,
+      fill: FillPolicy::Zero,
+      source: Some(Source::Hex(address, path.to_string())),
+    })
   }
 }
 
+pub fn read_file(path: &str) -> Result<Vec<u8>, String> {
+  let mut data = Vec::new();
+
+  File::open(path)
+    .and_then(|mut file| file.read_to_end(&mut data))
+    .map_err(|error| format!("Couldn't read {}: {}", path, error))?;
+
+  Ok(data)
+}
+
 impl Memory for BlockMemory {
   fn read(&self, address: u16) -> u8 {
     self.data[(address as usize) % self.size]
@@ -50,10 +132,35 @@ impl Memory for BlockMemory {
   fn tick(&mut self) {}
 
   fn reset(&mut self) {
-    if !self.persistent {
-      for i in 0..self.data.len() {
-        self.data[i] = 0;
-      }
+    if self.persistent {
+      return;
+    }
+
+    match self.fill {
+      FillPolicy::Zero => self.data.fill(0),
+      FillPolicy::Byte(value) => self.data.fill(value),
+      FillPolicy::Random(seed) => StdRng::seed_from_u64(seed).fill_bytes(&mut self.data),
+    }
+  }
+
+  fn reload(&mut self) -> Result<(), String> {
+    let reloaded = match &self.source {
+      Some(Source::File(path)) => BlockMemory::from_file(self.size, path)?,
+      Some(Source::Hex(address, path)) => BlockMemory::from_hex(self.size, *address, path)?,
+      None => return Ok(()),
+    };
+
+    self.data = reloaded.data;
+    Ok(())
+  }
+
+  fn save_state(&self) -> Vec<u8> {
+    self.data.clone()
+  }
+
+  fn load_state(&mut self, state: &[u8]) {
+    if state.len() == self.data.len() {
+      self.data.copy_from_slice(state);
     }
   }
 }
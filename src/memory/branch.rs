@@ -1,67 +1,370 @@
-use crate::memory::Memory;
+use crate::memory::{BusError, Memory};
+
+struct Region {
+  start: usize,
+  size: Option<usize>,
+  readonly: bool,
+  label: String,
+  memory: Box<dyn Memory>,
+  plugged: bool, // by map_device, on top of the rest
+}
+
+pub struct MappedRegion<'a> {
+  pub start: usize,
+  pub size: usize,
+  pub readonly: bool,
+  pub label: &'a str,
+}
+
+const UNMAPPED: u16 = u16::MAX;
 
 pub struct BranchMemory {
-  mapping: Vec<(usize, Box<dyn Memory>)>,
+  mapping: Vec<Region>,
+  table: Vec<u16>, // index of the region containing each address
 }
 
 impl BranchMemory {
   pub fn new() -> Self {
     Self {
       mapping: Vec::new(),
+      table: vec![UNMAPPED; 0x10000],
     }
   }
 
   pub fn map(mut self, address: usize, memory: Box<dyn Memory>) -> Self {
-    self.mapping.push((address, memory));
+    self.mapping.push(Region {
+      start: address,
+      size: None,
+      readonly: false,
+      label: String::new(),
+      memory,
+      plugged: false,
+    });
 
+    self.build_table();
     self
   }
-}
 
-impl Memory for BranchMemory {
-  fn read(&self, address: u16) -> u8 {
-    let mut memory = None;
-    let mut offset = 0;
+  // Like map, but ending after the given size rather than at the next region,
+  // leaving any gap unmapped
+  pub fn map_sized(mut self, address: usize, size: usize, memory: Box<dyn Memory>) -> Self {
+    self.mapping.push(Region {
+      start: address,
+      size: Some(size),
+      readonly: false,
+      label: String::new(),
+      memory,
+      plugged: false,
+    });
+
+    self.build_table();
+    self
+  }
+
+  // Writes to a read-only region are silently dropped
+  pub fn map_readonly(mut self, address: usize, size: usize, memory: Box<dyn Memory>) -> Self {
+    self.mapping.push(Region {
+      start: address,
+      size: Some(size),
+      readonly: true,
+      label: String::new(),
+      memory,
+      plugged: false,
+    });
 
-    for (start, mapped) in &self.mapping {
-      if address as usize >= *start {
-        memory = Some(mapped);
-        offset = *start as u16;
+    self.build_table();
+    self
+  }
+
+  // Name the most recently mapped region
+  pub fn label(mut self, label: &str) -> Self {
+    if let Some(region) = self.mapping.last_mut() {
+      region.label = label.to_string();
+    }
+
+    self
+  }
+
+  // Regions without an explicit size extend up to the next mapped region
+  pub fn iter_regions(&self) -> impl Iterator<Item = MappedRegion<'_>> {
+    self.mapping.iter().map(|region| {
+      let next = self
+        .mapping
+        .iter()
+        .filter(|other| !other.plugged)
+        .map(|other| other.start)
+        .filter(|&start| start > region.start)
+        .min()
+        .unwrap_or(0x10000);
+
+      MappedRegion {
+        start: region.start,
+        size: region.size.unwrap_or(next - region.start),
+        readonly: region.readonly,
+        label: &region.label,
       }
+    })
+  }
+
+  // Each address belongs to the region with the highest start at or below
+  // it, unless that region's explicit size ends first. Regions mapped at the
+  // same address replace the earlier ones. Devices from map_device go on top
+  // afterwards, only covering their own range.
+  fn build_table(&mut self) {
+    let mut order: Vec<usize> = (0..self.mapping.len())
+      .filter(|&index| !self.mapping[index].plugged)
+      .collect();
+    order.sort_by_key(|&index| self.mapping[index].start);
+
+    self.table.fill(UNMAPPED);
+
+    for &index in &order {
+      let region = &self.mapping[index];
+      let next = order
+        .iter()
+        .map(|&other| self.mapping[other].start)
+        .find(|&start| start > region.start)
+        .unwrap_or(0x10000);
+      let end = match region.size {
+        Some(size) => (region.start + size).min(next),
+        None => next,
+      };
+
+      self.table[region.start..next].fill(UNMAPPED);
+      self.table[region.start..end].fill(index as u16);
+    }
+
+    for (index, region) in self.mapping.iter().enumerate() {
+      if let (true, Some(size)) = (region.plugged, region.size) {
+        self.table[region.start..region.start + size].fill(index as u16);
+      }
+    }
+  }
+
+  // Returns the index of the mapped region containing the address
+  // along with the region's starting address
+  fn find(&self, address: u16) -> Option<(usize, u16)> {
+    match self.table[address as usize] {
+      UNMAPPED => None,
+      index => Some((index as usize, self.mapping[index as usize].start as u16)),
     }
+  }
+}
 
-    match memory {
-      Some(memory) => memory.read(address - offset),
+impl Memory for BranchMemory {
+  fn read(&self, address: u16) -> u8 {
+    match self.find(address) {
+      Some((index, offset)) => self.mapping[index].memory.read(address - offset),
       None => 0,
     }
   }
 
   fn write(&mut self, address: u16, value: u8) {
-    let mut memory = None;
-    let mut offset = 0;
+    if let Some((index, offset)) = self.find(address) {
+      let region = &mut self.mapping[index];
 
-    for (start, mapped) in &mut self.mapping {
-      if address as usize >= *start {
-        memory = Some(mapped);
-        offset = *start as u16;
+      if !region.readonly {
+        region.memory.write(address - offset, value);
       }
     }
-
-    match memory {
-      Some(memory) => memory.write(address - offset, value),
-      None => (),
-    };
   }
 
   fn tick(&mut self) {
-    for (_, mapped) in &mut self.mapping {
-      mapped.tick();
+    for region in &mut self.mapping {
+      region.memory.tick();
     }
   }
 
   fn reset(&mut self) {
-    for (_, mapped) in &mut self.mapping {
-      mapped.reset();
+    for region in &mut self.mapping {
+      region.memory.reset();
+    }
+  }
+
+  fn try_read(&self, address: u16) -> Result<u8, BusError> {
+    match self.find(address) {
+      Some((index, offset)) => self.mapping[index]
+        .memory
+        .try_read(address - offset)
+        .map_err(|_| BusError { address }),
+      None => Err(BusError { address }),
+    }
+  }
+
+  fn try_write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+    match self.find(address) {
+      Some((index, _)) if self.mapping[index].readonly => Err(BusError { address }),
+      Some((index, offset)) => self.mapping[index]
+        .memory
+        .try_write(address - offset, value)
+        .map_err(|_| BusError { address }),
+      None => Err(BusError { address }),
+    }
+  }
+
+  fn peek(&self, address: u16) -> u8 {
+    match self.find(address) {
+      Some((index, offset)) => self.mapping[index].memory.peek(address - offset),
+      None => 0,
     }
   }
+
+  fn mapped(&self, address: u16) -> bool {
+    match self.find(address) {
+      Some((index, offset)) => self.mapping[index].memory.mapped(address - offset),
+      None => false,
+    }
+  }
+
+  fn wait_states(&self, address: u16) -> u8 {
+    match self.find(address) {
+      Some((index, offset)) => self.mapping[index].memory.wait_states(address - offset),
+      None => 0,
+    }
+  }
+
+  fn map_device(
+    &mut self,
+    start: usize,
+    size: usize,
+    memory: Box<dyn Memory>,
+  ) -> Result<(), String> {
+    if size == 0 || start + size > 0x10000 {
+      return Err(format!(
+        "A device of {} bytes can't be mapped at {:04X}",
+        size, start
+      ));
+    }
+
+    self.mapping.push(Region {
+      start,
+      size: Some(size),
+      readonly: false,
+      label: String::new(),
+      memory,
+      plugged: true,
+    });
+
+    self.build_table();
+    Ok(())
+  }
+
+  // The most recently plugged device at the address goes, uncovering
+  // whatever it replaced. The regions the memory was built with stay put.
+  fn unmap_device(&mut self, start: usize) -> Option<Box<dyn Memory>> {
+    let index = self
+      .mapping
+      .iter()
+      .rposition(|region| region.plugged && region.start == start)?;
+    let region = self.mapping.remove(index);
+
+    self.build_table();
+    Some(region.memory)
+  }
+
+  fn reload(&mut self) -> Result<(), String> {
+    for region in &mut self.mapping {
+      region.memory.reload()?;
+    }
+
+    Ok(())
+  }
+
+  // Only the writable regions are saved, since ROMs are reloaded with the
+  // system, each one after its start, size and length so it can be put back
+  // in the same place even if the regions are mapped in a different order
+  fn save_state(&self) -> Vec<u8> {
+    let mut state = Vec::new();
+
+    for (region, mapped) in self.mapping.iter().zip(self.iter_regions()) {
+      if region.readonly {
+        continue;
+      }
+
+      let region_state = region.memory.save_state();
+      state.extend_from_slice(&(mapped.start as u32).to_le_bytes());
+      state.extend_from_slice(&(mapped.size as u32).to_le_bytes());
+      state.extend_from_slice(&(region_state.len() as u32).to_le_bytes());
+      state.extend_from_slice(&region_state);
+    }
+
+    state
+  }
+
+  // Regions in the state that aren't mapped here, or are mapped with a
+  // different size, are skipped
+  fn load_state(&mut self, mut state: &[u8]) {
+    let sizes: Vec<usize> = self.iter_regions().map(|region| region.size).collect();
+
+    while state.len() >= 12 {
+      let field = |index: usize| {
+        let bytes = &state[index * 4..index * 4 + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+      };
+      let (start, size, length) = (field(0), field(1), field(2));
+      let (region_state, rest) = state[12..].split_at(length.min(state.len() - 12));
+
+      // The last region mapped at an address is the one in use
+      let found = self
+        .mapping
+        .iter_mut()
+        .zip(&sizes)
+        .rev()
+        .find(|(region, &mapped)| !region.readonly && region.start == start && mapped == size);
+      if let Some((region, _)) = found {
+        region.memory.load_state(region_state);
+      }
+
+      state = rest;
+    }
+  }
+
+  fn next_event(&self) -> Option<u64> {
+    self
+      .mapping
+      .iter()
+      .filter_map(|region| region.memory.next_event())
+      .min()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::memory::BlockMemory;
+
+  fn filled(value: u8, size: usize) -> Box<dyn Memory> {
+    Box::new(BlockMemory::from_bytes(&vec![value; size]))
+  }
+
+  #[test]
+  fn devices_are_read_through_while_mapped() {
+    let mut memory = BranchMemory::new()
+      .map(0x0000, filled(0x11, 0x8000))
+      .map_readonly(0x8000, 0x8000, filled(0x22, 0x8000));
+
+    memory
+      .map_device(0x8000, 0x100, filled(0x33, 0x100))
+      .unwrap();
+    assert_eq!(memory.read(0x7FFF), 0x11);
+    assert_eq!(memory.read(0x8000), 0x33);
+    assert_eq!(memory.read(0x80FF), 0x33);
+    assert_eq!(memory.read(0x8100), 0x22);
+
+    let device = memory.unmap_device(0x8000).unwrap();
+    assert_eq!(device.read(0x0000), 0x33);
+    assert_eq!(memory.read(0x8000), 0x22);
+  }
+
+  #[test]
+  fn unmapping_leaves_the_base_regions() {
+    let mut memory = BranchMemory::new().map(0x0000, filled(0x11, 0x10000));
+    assert!(memory.unmap_device(0x0000).is_none());
+    assert_eq!(memory.read(0x0000), 0x11);
+
+    memory.map_device(0x0000, 0x10, filled(0x33, 0x10)).unwrap();
+    assert!(memory.unmap_device(0x0000).is_some());
+    assert!(memory.unmap_device(0x0000).is_none());
+    assert_eq!(memory.read(0x0000), 0x11);
+  }
 }
@@ -0,0 +1,133 @@
+use crate::memory::Memory;
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Wraps a device that runs on a different clock to the CPU, like a video chip
+// at 8x the CPU's rate. Each tick catches the device up to the cycle count on
+// a clock shared with the System (see System::clock), ticking it
+// multiplier / divider times per CPU cycle. The System ticks its devices
+// before each instruction, so the device runs an instruction behind: it's
+// only caught up to the cycle the instruction it's ticked for starts on.
+pub struct ClockedMemory {
+  memory: Box<dyn Memory>,
+  clock: Rc<Cell<u64>>,
+  multiplier: u64,
+  divider: u64,
+  ticks: u64,   // given to the device so far
+  warned: bool, // that nothing else holds the clock
+}
+
+impl ClockedMemory {
+  pub fn new(memory: Box<dyn Memory>, clock: Rc<Cell<u64>>, multiplier: u64, divider: u64) -> Self {
+    assert!(
+      multiplier > 0 && divider > 0,
+      "Clock ratios must be positive"
+    );
+
+    Self {
+      memory,
+      clock,
+      multiplier,
+      divider,
+      ticks: 0,
+      warned: false,
+    }
+  }
+
+  // How many times the device should have ticked by now
+  fn due(&self) -> u64 {
+    self.clock.get() * self.multiplier / self.divider
+  }
+}
+
+impl Memory for ClockedMemory {
+  fn tick(&mut self) {
+    // A clock that was never given to a System can't advance, so the device
+    // would never tick
+    if !self.warned && Rc::strong_count(&self.clock) == 1 {
+      eprintln!(
+        "The clock of a ClockedMemory isn't shared, so it will never tick (see System::clock)"
+      );
+      self.warned = true;
+    }
+
+    while self.ticks < self.due() {
+      self.memory.tick();
+      self.ticks += 1;
+    }
+  }
+
+  fn reset(&mut self) {
+    self.memory.reset();
+    self.ticks = self.due();
+  }
+
+  // The device counts in its own ticks, so convert back to CPU cycles
+  fn next_event(&self) -> Option<u64> {
+    self
+      .memory
+      .next_event()
+      .map(|ticks| (ticks * self.divider).div_ceil(self.multiplier))
+  }
+
+  forward_memory!(self => self.memory, self.memory;
+    read, write, try_read, try_write, peek, mapped, wait_states, reload, save_state, load_state,
+    map_device, unmap_device);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::memory::BlockMemory;
+  use crate::system::System;
+
+  // RAM that counts its ticks
+  struct TickCounter {
+    ram: BlockMemory,
+    ticks: Rc<Cell<u64>>,
+  }
+
+  impl Memory for TickCounter {
+    fn read(&self, address: u16) -> u8 {
+      self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+      self.ram.write(address, value);
+    }
+
+    fn tick(&mut self) {
+      self.ticks.set(self.ticks.get() + 1);
+    }
+
+    fn reset(&mut self) {
+      self.ram.reset();
+    }
+  }
+
+  #[test]
+  fn ticks_twice_per_cycle_an_instruction_behind() {
+    let ticks = Rc::new(Cell::new(0));
+    let clock = Rc::new(Cell::new(0));
+    let counter = TickCounter {
+      ram: BlockMemory::ram(0x10000),
+      ticks: Rc::clone(&ticks),
+    };
+
+    let memory = ClockedMemory::new(Box::new(counter), Rc::clone(&clock), 2, 1);
+    let mut system = System::new(Box::new(memory));
+    system.clock(clock);
+    system.nop_fill(0x0200, 11);
+    system.registers.pc.load(0x0200);
+
+    for _ in 0..10 {
+      system.try_tick().unwrap();
+    }
+    assert_eq!(system.cycles(), 20);
+    assert_eq!(ticks.get(), 36);
+
+    // Catching up to the end of the last NOP before the next one
+    system.try_tick().unwrap();
+    assert_eq!(ticks.get(), 40);
+  }
+}
@@ -0,0 +1,71 @@
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Charset {
+  Ascii,
+  Petscii,
+}
+
+// Character output port for headless text programs: every byte written is
+// appended to a buffer, and optionally echoed to stdout. Reads return a
+// status byte with the high bit set to show the port is always ready.
+// All clones append to one buffer, so take_output on a clone made before
+// mapping the port returns what the program printed.
+#[derive(Clone)]
+pub struct ConsoleMemory {
+  charset: Charset,
+  echo: bool,
+  output: Rc<RefCell<String>>,
+}
+
+impl ConsoleMemory {
+  pub fn new(charset: Charset, echo: bool) -> Self {
+    Self {
+      charset,
+      echo,
+      output: Rc::new(RefCell::new(String::new())),
+    }
+  }
+
+  // Everything printed since the last call
+  pub fn take_output(&self) -> String {
+    self.output.borrow_mut().split_off(0)
+  }
+
+  fn translate(&self, value: u8) -> Option<char> {
+    match (self.charset, value) {
+      (_, 0x0D) => Some('\n'),
+      (Charset::Ascii, 0x20..=0x7E | 0x0A | 0x09) => Some(value as char),
+      // Shifted and unshifted letters both come out as upper case, since
+      // that's how they appear in the default character set
+      (Charset::Petscii, 0x41..=0x5A) => Some(value as char),
+      (Charset::Petscii, 0xC1..=0xDA) => Some((value - 0x80) as char),
+      (Charset::Petscii, 0x20..=0x40 | 0x5B | 0x5D) => Some(value as char),
+      _ => None,
+    }
+  }
+}
+
+impl Memory for ConsoleMemory {
+  fn read(&self, _address: u16) -> u8 {
+    0x80
+  }
+
+  fn write(&mut self, _address: u16, value: u8) {
+    if let Some(char) = self.translate(value) {
+      self.output.borrow_mut().push(char);
+
+      if self.echo {
+        print!("{}", char);
+        std::io::stdout().flush().unwrap();
+      }
+    }
+  }
+
+  fn tick(&mut self) {}
+
+  fn reset(&mut self) {}
+}
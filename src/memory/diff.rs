@@ -0,0 +1,10 @@
+// Each address at which two memory dumps differ, with the value from each.
+// Only the overlapping part is compared if they aren't the same length.
+pub fn diff(a: &[u8], b: &[u8]) -> Vec<(u16, u8, u8)> {
+  a.iter()
+    .zip(b.iter())
+    .enumerate()
+    .filter(|(_, (x, y))| x != y)
+    .map(|(address, (&x, &y))| (address as u16, x, y))
+    .collect()
+}
@@ -0,0 +1,107 @@
+use crate::memory::Memory;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const SECTOR_SIZE: usize = 0x100;
+
+const READY: u8 = 0x80;
+const ERROR: u8 = 0x01;
+
+// A disk drive backed by a host file, for programs that load more of
+// themselves while running. A program picks a sector, writes a command, then
+// waits for the ready bit; the transfer happens on the next tick.
+//   0x000: command (1 reads the sector into the buffer, 2 writes the buffer
+//          to it) when written, status (bit 7 ready, bit 0 error) when read
+//   0x001: sector number, low byte
+//   0x002: sector number, high byte
+//   0x100-0x1FF: the sector buffer
+// Sectors past the end of the file read as zeros, and writing them grows the
+// file. The device takes 0x200 bytes, and is mirrored after that.
+pub struct BlockDeviceMemory {
+  file: File,
+  sector: u16,
+  command: Option<u8>, // waiting for the next tick
+  status: u8,
+  buffer: [u8; SECTOR_SIZE],
+}
+
+impl BlockDeviceMemory {
+  pub fn new(path: &str) -> Result<Self, String> {
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(path)
+      .map_err(|error| format!("Couldn't open {}: {}", path, error))?;
+
+    Ok(Self {
+      file,
+      sector: 0,
+      command: None,
+      status: READY,
+      buffer: [0; SECTOR_SIZE],
+    })
+  }
+
+  fn transfer(&mut self, command: u8) -> std::io::Result<()> {
+    let offset = self.sector as u64 * SECTOR_SIZE as u64;
+    self.file.seek(SeekFrom::Start(offset))?;
+
+    match command {
+      0x01 => {
+        self.buffer = [0; SECTOR_SIZE];
+        let mut filled = 0;
+        while filled < SECTOR_SIZE {
+          match self.file.read(&mut self.buffer[filled..])? {
+            0 => break, // past the end of the file
+            read => filled += read,
+          }
+        }
+        Ok(())
+      }
+      0x02 => self.file.write_all(&self.buffer),
+      _ => Err(std::io::ErrorKind::InvalidInput.into()),
+    }
+  }
+}
+
+impl Memory for BlockDeviceMemory {
+  fn read(&self, address: u16) -> u8 {
+    match address as usize % (2 * SECTOR_SIZE) {
+      0x000 => self.status,
+      0x001 => self.sector as u8,
+      0x002 => (self.sector >> 8) as u8,
+      offset if offset >= SECTOR_SIZE => self.buffer[offset - SECTOR_SIZE],
+      _ => 0,
+    }
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    match address as usize % (2 * SECTOR_SIZE) {
+      0x000 => {
+        self.command = Some(value);
+        self.status = 0;
+      }
+      0x001 => self.sector = (self.sector & 0xFF00) | value as u16,
+      0x002 => self.sector = (self.sector & 0x00FF) | (value as u16) << 8,
+      offset if offset >= SECTOR_SIZE => self.buffer[offset - SECTOR_SIZE] = value,
+      _ => {}
+    }
+  }
+
+  fn tick(&mut self) {
+    if let Some(command) = self.command.take() {
+      self.status = match self.transfer(command) {
+        Ok(()) => READY,
+        Err(_) => READY | ERROR,
+      };
+    }
+  }
+
+  fn reset(&mut self) {
+    self.sector = 0;
+    self.command = None;
+    self.status = READY;
+  }
+}
@@ -1,12 +1,17 @@
+#[cfg(feature = "graphics")]
 use crate::graphics::{Color, GraphicsProvider};
+use crate::input::{InputProvider, KeyEvent};
 use crate::memory::Memory;
 use rand::random;
+#[cfg(feature = "graphics")]
 use std::cell::RefCell;
+#[cfg(feature = "graphics")]
 use std::rc::Rc;
 
 // Easy6502 bitmap screen memory
 // https://skilldrick.github.io/easy6502/
 
+#[cfg(feature = "graphics")]
 pub struct EasyVram {
   width: u32,
   height: u32,
@@ -15,8 +20,10 @@ pub struct EasyVram {
   palette: Vec<Color>,
 }
 
+#[cfg(feature = "graphics")]
 const SCALE: u32 = 8;
 
+#[cfg(feature = "graphics")]
 impl EasyVram {
   pub fn new(width: u32, height: u32, graphics: Rc<RefCell<Box<dyn GraphicsProvider>>>) -> Self {
     graphics
@@ -43,6 +50,7 @@ impl EasyVram {
   }
 }
 
+#[cfg(feature = "graphics")]
 impl Memory for EasyVram {
   fn read(&self, address: u16) -> u8 {
     self.data[((address as u32) % (self.width * self.height)) as usize]
@@ -71,12 +79,13 @@ impl Memory for EasyVram {
 }
 
 pub struct EasyIO {
-  graphics: Rc<RefCell<Box<dyn GraphicsProvider>>>,
+  input: Box<dyn InputProvider>,
+  last_key: u8,
 }
 
 impl EasyIO {
-  pub fn new(graphics: Rc<RefCell<Box<dyn GraphicsProvider>>>) -> Self {
-    Self { graphics }
+  pub fn new(input: Box<dyn InputProvider>) -> Self {
+    Self { input, last_key: 0 }
   }
 }
 
@@ -84,13 +93,29 @@ impl Memory for EasyIO {
   fn read(&self, address: u16) -> u8 {
     match address % 2 {
       0 => random::<u8>(),
-      _ => self.graphics.borrow().get_last_key(),
+      _ => self.last_key,
     }
   }
 
   fn write(&mut self, _address: u16, _value: u8) {}
 
-  fn tick(&mut self) {}
+  // Leave the random number generator alone
+  fn peek(&self, address: u16) -> u8 {
+    match address % 2 {
+      0 => 0,
+      _ => self.last_key,
+    }
+  }
+
+  fn tick(&mut self) {
+    self.input.tick();
+
+    while let Some(event) = self.input.poll() {
+      if let KeyEvent::Down(key) = event {
+        self.last_key = key;
+      }
+    }
+  }
 
   fn reset(&mut self) {}
 }
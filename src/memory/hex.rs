@@ -0,0 +1,263 @@
+use std::path::Path;
+
+// The contents of an Intel HEX or Motorola S-record file, laid out over the
+// whole address space
+pub struct HexImage {
+  pub data: Vec<u8>,
+  pub entry: Option<u16>,
+}
+
+// Whether the file's extension marks it as a hex image rather than a raw binary
+pub fn is_hex_file(path: &str) -> bool {
+  let extension = Path::new(path)
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .map(|extension| extension.to_ascii_lowercase());
+
+  matches!(
+    extension.as_deref(),
+    Some("hex" | "ihex" | "ihx" | "srec" | "s19" | "s28" | "s37" | "mot")
+  )
+}
+
+pub fn read_hex(path: &str) -> Result<HexImage, String> {
+  let text =
+    std::fs::read_to_string(path).map_err(|error| format!("Couldn't read {}: {}", path, error))?;
+
+  let mut image = HexImage {
+    data: vec![0; 0x10000],
+    entry: None,
+  };
+
+  for (number, line) in text.lines().enumerate() {
+    let line = line.trim();
+
+    let result = match line.chars().next() {
+      None => continue,
+      Some(':') => intel_record(&mut image, &line[1..]),
+      Some('S') => srec_record(&mut image, &line[1..]),
+      Some(_) => Err("not an Intel HEX or S-record line".to_string()),
+    };
+
+    match result {
+      Ok(true) => break, // end of file record
+      Ok(false) => {}
+      Err(error) => return Err(format!("{}, line {}: {}", path, number + 1, error)),
+    }
+  }
+
+  Ok(image)
+}
+
+fn parse_bytes(hex: &str) -> Result<Vec<u8>, String> {
+  if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    return Err("invalid hex digits".to_string());
+  }
+
+  Ok(
+    (0..hex.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+      .collect(),
+  )
+}
+
+fn load(image: &mut HexImage, address: u32, data: &[u8]) -> Result<(), String> {
+  if address as usize + data.len() > image.data.len() {
+    return Err(format!(
+      "data at {:X} is outside the address space",
+      address
+    ));
+  }
+
+  image.data[address as usize..address as usize + data.len()].copy_from_slice(data);
+  Ok(())
+}
+
+fn entry(address: u32) -> Result<Option<u16>, String> {
+  match u16::try_from(address) {
+    Ok(address) => Ok(Some(address)),
+    Err(_) => Err(format!(
+      "entry point {:X} is outside the address space",
+      address
+    )),
+  }
+}
+
+// Returns whether this was the end of file record
+fn intel_record(image: &mut HexImage, record: &str) -> Result<bool, String> {
+  // Length, address, type, data, checksum
+  let bytes = parse_bytes(record)?;
+  if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+    return Err("record length doesn't match its byte count".to_string());
+  }
+
+  if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+    return Err("bad checksum".to_string());
+  }
+
+  let address = (bytes[1] as u32) << 8 | bytes[2] as u32;
+  let data = &bytes[4..bytes.len() - 1];
+
+  match bytes[3] {
+    0x00 => load(image, address, data)?,
+    0x01 => return Ok(true),
+    // Extended segment and linear addresses, which only fit if they're zero
+    0x02 | 0x04 => {
+      if data.iter().any(|&byte| byte != 0) {
+        return Err("extended addresses are outside the address space".to_string());
+      }
+    }
+    // Start segment (CS:IP) and linear addresses
+    0x03 | 0x05 if data.len() == 4 => {
+      let value = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+      image.entry = match bytes[3] {
+        0x03 => entry((value >> 16) * 16 + (value & 0xFFFF))?,
+        _ => entry(value)?,
+      };
+    }
+    kind => return Err(format!("unsupported record type {:02X}", kind)),
+  }
+
+  Ok(false)
+}
+
+fn srec_record(image: &mut HexImage, record: &str) -> Result<bool, String> {
+  let kind = record.chars().next().ok_or("missing record type")?;
+
+  let address_size = match kind {
+    '0' | '1' | '5' | '9' => 2,
+    '2' | '6' | '8' => 3,
+    '3' | '7' => 4,
+    _ => return Err(format!("unsupported record type S{}", kind)),
+  };
+
+  // Count, address, data, checksum
+  let bytes = parse_bytes(&record[1..])?;
+  if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+    return Err("record length doesn't match its byte count".to_string());
+  }
+
+  if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xFF {
+    return Err("bad checksum".to_string());
+  }
+
+  if bytes.len() < address_size + 2 {
+    return Err("record is too short for its address".to_string());
+  }
+
+  let address = bytes[1..=address_size]
+    .iter()
+    .fold(0u32, |address, &byte| address << 8 | byte as u32);
+  let data = &bytes[address_size + 1..bytes.len() - 1];
+
+  match kind {
+    '1' | '2' | '3' => load(image, address, data)?,
+    '7' | '8' | '9' => {
+      image.entry = entry(address)?;
+      return Ok(true);
+    }
+    _ => {} // header and record counts
+  }
+
+  Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read(name: &str, text: &str) -> Result<HexImage, String> {
+    let path = std::env::temp_dir().join(format!("noentiendo-{}-{}", std::process::id(), name));
+    std::fs::write(&path, text).unwrap();
+    let result = read_hex(path.to_str().unwrap());
+    std::fs::remove_file(&path).unwrap();
+    result
+  }
+
+  fn error(name: &str, text: &str) -> String {
+    let error = read(name, text).err().unwrap();
+    // Drop the path, which depends on the temp directory
+    error[error.find(", line ").unwrap() + 2..].to_string()
+  }
+
+  #[test]
+  fn intel_hex() {
+    let image = read(
+      "good.hex",
+      ":03800000A9426032\n:040000050000800077\n:00000001FF\n",
+    )
+    .unwrap();
+
+    assert_eq!(image.data[0x8000..0x8003], [0xA9, 0x42, 0x60]);
+    assert_eq!(image.data[0x8003], 0);
+    assert_eq!(image.entry, Some(0x8000));
+  }
+
+  #[test]
+  fn intel_hex_stops_at_the_end_of_file() {
+    let image = read("end.hex", ":00000001FF\nnot a record\n").unwrap();
+    assert_eq!(image.entry, None);
+  }
+
+  #[test]
+  fn intel_hex_bad_checksum() {
+    assert_eq!(
+      error("checksum.hex", ":03800000A9426033\n"),
+      "line 1: bad checksum"
+    );
+  }
+
+  #[test]
+  fn intel_hex_malformed_records() {
+    assert_eq!(
+      error("length.hex", ":03800000A94260\n"),
+      "line 1: record length doesn't match its byte count"
+    );
+    assert_eq!(
+      error("digits.hex", ":03800000A94260G2\n"),
+      "line 1: invalid hex digits"
+    );
+    assert_eq!(
+      error("type.hex", ":03800000A9426032\n:00000006FA\n"),
+      "line 2: unsupported record type 06"
+    );
+    assert_eq!(
+      error("line.hex", "03800000A9426032\n"),
+      "line 1: not an Intel HEX or S-record line"
+    );
+  }
+
+  #[test]
+  fn srecord() {
+    let image = read("good.s19", "S1068000A942602E\nS90380007C\n").unwrap();
+
+    assert_eq!(image.data[0x8000..0x8003], [0xA9, 0x42, 0x60]);
+    assert_eq!(image.entry, Some(0x8000));
+  }
+
+  #[test]
+  fn srecord_bad_checksum() {
+    assert_eq!(
+      error("checksum.s19", "S1068000A942602F\n"),
+      "line 1: bad checksum"
+    );
+  }
+
+  #[test]
+  fn srecord_malformed_records() {
+    assert_eq!(
+      error("length.s19", "S1058000A942602E\n"),
+      "line 1: record length doesn't match its byte count"
+    );
+    assert_eq!(
+      error("digits.s19", "S1068000A942602\n"),
+      "line 1: invalid hex digits"
+    );
+    assert_eq!(
+      error("type.s19", "S1068000A942602E\nS4030000FC\n"),
+      "line 2: unsupported record type S4"
+    );
+    assert_eq!(error("missing.s19", "S\n"), "line 1: missing record type");
+  }
+}
@@ -1,15 +1,136 @@
+// Write out the Memory methods a wrapper passes straight through to the
+// memory it wraps, given how to get at that memory from &self and from
+// &mut self, so a wrapper only spells out the methods it changes. Every
+// method the trait has should be listed here, so none are missed when a
+// wrapper forwards everything else.
+macro_rules! forward_memory {
+  ($this:ident => $memory:expr, $memory_mut:expr; $($method:ident),* $(,)?) => {
+    $(forward_memory!(@$method $this, $memory, $memory_mut);)*
+  };
+  (@read $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn read(&$this, address: u16) -> u8 {
+      $memory.read(address)
+    }
+  };
+  (@write $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn write(&mut $this, address: u16, value: u8) {
+      $memory_mut.write(address, value);
+    }
+  };
+  (@tick $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn tick(&mut $this) {
+      $memory_mut.tick();
+    }
+  };
+  (@reset $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn reset(&mut $this) {
+      $memory_mut.reset();
+    }
+  };
+  (@try_read $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn try_read(&$this, address: u16) -> Result<u8, $crate::memory::BusError> {
+      $memory.try_read(address)
+    }
+  };
+  (@try_write $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn try_write(&mut $this, address: u16, value: u8) -> Result<(), $crate::memory::BusError> {
+      $memory_mut.try_write(address, value)
+    }
+  };
+  (@peek $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn peek(&$this, address: u16) -> u8 {
+      $memory.peek(address)
+    }
+  };
+  (@mapped $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn mapped(&$this, address: u16) -> bool {
+      $memory.mapped(address)
+    }
+  };
+  (@next_event $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn next_event(&$this) -> Option<u64> {
+      $memory.next_event()
+    }
+  };
+  (@wait_states $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn wait_states(&$this, address: u16) -> u8 {
+      $memory.wait_states(address)
+    }
+  };
+  (@reload $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn reload(&mut $this) -> Result<(), String> {
+      $memory_mut.reload()
+    }
+  };
+  (@save_state $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn save_state(&$this) -> Vec<u8> {
+      $memory.save_state()
+    }
+  };
+  (@load_state $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn load_state(&mut $this, state: &[u8]) {
+      $memory_mut.load_state(state);
+    }
+  };
+  (@map_device $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn map_device(
+      &mut $this,
+      start: usize,
+      size: usize,
+      memory: Box<dyn $crate::memory::Memory>,
+    ) -> Result<(), String> {
+      $memory_mut.map_device(start, size, memory)
+    }
+  };
+  (@unmap_device $this:ident, $memory:expr, $memory_mut:expr) => {
+    fn unmap_device(&mut $this, start: usize) -> Option<Box<dyn $crate::memory::Memory>> {
+      $memory_mut.unmap_device(start)
+    }
+  };
+}
+
+mod analog;
 mod block;
 mod branch;
+mod clocked;
+mod console;
+mod diff;
+mod disk;
 pub mod easy;
+mod hex;
 mod null;
+#[cfg(feature = "graphics")]
 pub mod pet;
+mod profiling;
+mod scripted;
+mod shared;
+mod speaker;
 mod stdio;
 pub mod systems;
+mod trap;
+mod write_only;
 
-pub use block::BlockMemory;
-pub use branch::BranchMemory;
+pub use analog::AnalogInputMemory;
+pub use block::{read_file, BlockMemory, FillPolicy};
+pub use branch::{BranchMemory, MappedRegion};
+pub use clocked::ClockedMemory;
+pub use console::{Charset, ConsoleMemory};
+pub use diff::diff;
+pub use disk::BlockDeviceMemory;
+pub use hex::{is_hex_file, read_hex, HexImage};
 pub use null::NullMemory;
+pub use profiling::ProfilingMemory;
+pub use scripted::ScriptedMemory;
+pub use shared::SharedMemory;
+pub use speaker::SpeakerMemory;
 pub use stdio::MappedStdIO;
+pub use trap::TrapMemory;
+pub use write_only::WriteOnlyMemory;
+
+#[derive(Debug)]
+pub struct BusError {
+  pub address: u16,
+}
 
 // Commodore PET-style column screen memory
 // (see https://www.chibiakumas.com/6502/platform4.php#LessonP38 for details)
@@ -19,4 +140,68 @@ pub trait Memory {
   fn write(&mut self, address: u16, value: u8);
   fn tick(&mut self);
   fn reset(&mut self);
+
+  // Like read, but reports accesses to devices that aren't actually present
+  fn try_read(&self, address: u16) -> Result<u8, BusError> {
+    Ok(self.read(address))
+  }
+
+  // Like write, but reports writes that go nowhere, like into ROM
+  fn try_write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+    self.write(address, value);
+    Ok(())
+  }
+
+  // Like read, but without side effects on the device, for inspecting memory
+  fn peek(&self, address: u16) -> u8 {
+    self.read(address)
+  }
+
+  // Whether there's a device at the address for try_read to succeed on,
+  // asked without reading from it
+  fn mapped(&self, _address: u16) -> bool {
+    true
+  }
+
+  // How many cycles from now the device next needs to do something, like
+  // raising an interrupt from a timer, or None if it's only driven by the CPU
+  fn next_event(&self) -> Option<u64> {
+    None
+  }
+
+  // Extra cycles the CPU is stalled for when accessing the address, like
+  // video memory that's busy during the active display
+  fn wait_states(&self, _address: u16) -> u8 {
+    0
+  }
+
+  // Read the contents of the device again from wherever they were loaded
+  // from, like a ROM image that's been rebuilt
+  fn reload(&mut self) -> Result<(), String> {
+    Ok(())
+  }
+
+  // The contents of the device, for save states. Devices that don't keep
+  // any are left as they are when a state is loaded.
+  fn save_state(&self) -> Vec<u8> {
+    Vec::new()
+  }
+
+  fn load_state(&mut self, _state: &[u8]) {}
+
+  // Plug a device into the address space while the system is running, like
+  // a cartridge being inserted. Only a BranchMemory has anywhere to put it.
+  fn map_device(
+    &mut self,
+    _start: usize,
+    _size: usize,
+    _memory: Box<dyn Memory>,
+  ) -> Result<(), String> {
+    Err("Devices can only be mapped into a BranchMemory".to_string())
+  }
+
+  // Unplug the device mapped at the address, handing it back
+  fn unmap_device(&mut self, _start: usize) -> Option<Box<dyn Memory>> {
+    None
+  }
 }
@@ -1,4 +1,4 @@
-use crate::memory::Memory;
+use crate::memory::{BusError, Memory};
 
 pub struct NullMemory {}
 
@@ -18,4 +18,16 @@ impl Memory for NullMemory {
   fn tick(&mut self) {}
 
   fn reset(&mut self) {}
+
+  fn try_read(&self, address: u16) -> Result<u8, BusError> {
+    Err(BusError { address })
+  }
+
+  fn try_write(&mut self, address: u16, _value: u8) -> Result<(), BusError> {
+    Err(BusError { address })
+  }
+
+  fn mapped(&self, _address: u16) -> bool {
+    false
+  }
 }
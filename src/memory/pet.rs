@@ -1,8 +1,6 @@
 use crate::graphics::{Color, GraphicsProvider};
-use crate::memory::Memory;
+use crate::memory::{read_file, Memory};
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::Read;
 use std::rc::Rc;
 
 const WIDTH: u32 = 40;
@@ -20,22 +18,31 @@ pub struct PetVram {
 }
 
 impl PetVram {
-  pub fn new(rom_path: &str, graphics: Rc<RefCell<Box<dyn GraphicsProvider>>>) -> Self {
-    let mut file = File::open(rom_path).unwrap();
-    let mut character_rom = Vec::new();
-    file.read_to_end(&mut character_rom).unwrap();
+  pub fn new(
+    rom_path: &str,
+    graphics: Rc<RefCell<Box<dyn GraphicsProvider>>>,
+  ) -> Result<Self, String> {
+    let character_rom = read_file(rom_path)?;
+
+    if character_rom.len() < 256 * 8 {
+      return Err(format!(
+        "{} is too small to be a character ROM ({} bytes)",
+        rom_path,
+        character_rom.len()
+      ));
+    }
 
     graphics
       .borrow_mut()
       .create_window(WIDTH * CHAR_WIDTH, HEIGHT * CHAR_HEIGHT, 2.0);
 
-    Self {
+    Ok(Self {
       data: vec![0; VRAM_SIZE],
       graphics,
       character_rom,
       foreground: Color::new(255, 255, 255),
       background: Color::new(0, 0, 255),
-    }
+    })
   }
 }
 
@@ -0,0 +1,75 @@
+use crate::memory::{BusError, Memory};
+use std::cell::Cell;
+
+// Wraps another memory, counting how often each address is accessed
+pub struct ProfilingMemory {
+  memory: Box<dyn Memory>,
+  reads: Vec<Cell<u64>>,
+  writes: Vec<u64>,
+}
+
+impl ProfilingMemory {
+  pub fn new(memory: Box<dyn Memory>) -> Self {
+    Self {
+      memory,
+      reads: vec![Cell::new(0); 0x10000],
+      writes: vec![0; 0x10000],
+    }
+  }
+
+  // The most accessed addresses, as (address, reads, writes)
+  pub fn hottest(&self, count: usize) -> Vec<(u16, u64, u64)> {
+    let mut accesses: Vec<(u16, u64, u64)> = (0..0x10000)
+      .map(|address| {
+        (
+          address as u16,
+          self.reads[address].get(),
+          self.writes[address],
+        )
+      })
+      .filter(|&(_, reads, writes)| reads + writes > 0)
+      .collect();
+
+    accesses.sort_by_key(|&(address, reads, writes)| (std::cmp::Reverse(reads + writes), address));
+    accesses.truncate(count);
+    accesses
+  }
+
+  pub fn print_report(&self, count: usize) {
+    println!("Address    Reads   Writes");
+    for (address, reads, writes) in self.hottest(count) {
+      println!("{:04X}    {:>8} {:>8}", address, reads, writes);
+    }
+  }
+
+  fn count_read(&self, address: u16) {
+    let counter = &self.reads[address as usize];
+    counter.set(counter.get() + 1);
+  }
+}
+
+impl Memory for ProfilingMemory {
+  fn read(&self, address: u16) -> u8 {
+    self.count_read(address);
+    self.memory.read(address)
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    self.writes[address as usize] += 1;
+    self.memory.write(address, value);
+  }
+
+  fn try_read(&self, address: u16) -> Result<u8, BusError> {
+    self.count_read(address);
+    self.memory.try_read(address)
+  }
+
+  fn try_write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+    self.writes[address as usize] += 1;
+    self.memory.try_write(address, value)
+  }
+
+  forward_memory!(self => self.memory, self.memory;
+    tick, reset, peek, mapped, wait_states, next_event, reload, save_state, load_state,
+    map_device, unmap_device);
+}
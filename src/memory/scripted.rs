@@ -0,0 +1,57 @@
+use crate::memory::Memory;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+// Answers reads with a fixed sequence of bytes, whatever the address, and
+// records every write, for checking an instruction on its own: script the
+// opcode, its operands and any data it reads, step once, then look at the
+// registers and the writes. Reads past the end of the script return 0, and
+// peeks show the next byte without using it up. Check remaining and writes
+// on a clone made before handing it to the System, as both work through the
+// same script.
+#[derive(Clone)]
+pub struct ScriptedMemory {
+  script: Rc<Vec<u8>>,
+  position: Rc<Cell<usize>>,
+  writes: Rc<RefCell<Vec<(u16, u8)>>>,
+}
+
+impl ScriptedMemory {
+  pub fn new(script: &[u8]) -> Self {
+    Self {
+      script: Rc::new(script.to_vec()),
+      position: Rc::new(Cell::new(0)),
+      writes: Rc::new(RefCell::new(Vec::new())),
+    }
+  }
+
+  // How many scripted bytes haven't been read yet
+  pub fn remaining(&self) -> usize {
+    self.script.len().saturating_sub(self.position.get())
+  }
+
+  // Every write so far, as the address and value, in order
+  pub fn writes(&self) -> Vec<(u16, u8)> {
+    self.writes.borrow().clone()
+  }
+}
+
+impl Memory for ScriptedMemory {
+  fn read(&self, _address: u16) -> u8 {
+    let value = self.peek(0);
+    self.position.set(self.position.get() + 1);
+    value
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    self.writes.borrow_mut().push((address, value));
+  }
+
+  fn tick(&mut self) {}
+
+  fn reset(&mut self) {}
+
+  fn peek(&self, _address: u16) -> u8 {
+    self.script.get(self.position.get()).copied().unwrap_or(0)
+  }
+}
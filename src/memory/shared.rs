@@ -0,0 +1,47 @@
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Memory that several CPUs can be attached to, by giving each System its own
+// handle. Clones share the underlying memory, but only the original ticks and
+// resets it, so the devices run once per step however many CPUs there are.
+pub struct SharedMemory {
+  memory: Rc<RefCell<Box<dyn Memory>>>,
+  primary: bool,
+}
+
+impl SharedMemory {
+  pub fn new(memory: Box<dyn Memory>) -> Self {
+    Self {
+      memory: Rc::new(RefCell::new(memory)),
+      primary: true,
+    }
+  }
+}
+
+impl Clone for SharedMemory {
+  fn clone(&self) -> Self {
+    Self {
+      memory: Rc::clone(&self.memory),
+      primary: false,
+    }
+  }
+}
+
+impl Memory for SharedMemory {
+  fn tick(&mut self) {
+    if self.primary {
+      self.memory.borrow_mut().tick();
+    }
+  }
+
+  fn reset(&mut self) {
+    if self.primary {
+      self.memory.borrow_mut().reset();
+    }
+  }
+
+  forward_memory!(self => self.memory.borrow(), self.memory.borrow_mut();
+    read, write, try_read, try_write, peek, mapped, wait_states, next_event, reload, save_state,
+    load_state, map_device, unmap_device);
+}
@@ -0,0 +1,80 @@
+use crate::memory::Memory;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+// A one-bit speaker, like the Apple II's: bit 0 of each write sets the
+// position of the speaker cone, and reads return it. Every change is recorded
+// with the cycle it happened on, read from a clock shared with the System
+// (see System::clock), so the host can render the waveform at its own rate.
+// To render it, keep a clone back when mapping the speaker: every clone sees
+// the same recording.
+#[derive(Clone)]
+pub struct SpeakerMemory {
+  clock: Rc<Cell<u64>>,
+  level: Rc<Cell<bool>>,
+  toggles: Rc<RefCell<VecDeque<(u64, bool)>>>,
+  sampled: Rc<Cell<bool>>, // the level as of the last sample
+}
+
+impl SpeakerMemory {
+  pub fn new(clock: Rc<Cell<u64>>) -> Self {
+    Self {
+      clock,
+      level: Rc::new(Cell::new(false)),
+      toggles: Rc::new(RefCell::new(VecDeque::new())),
+      sampled: Rc::new(Cell::new(false)),
+    }
+  }
+
+  // Every change since the last call, as the cycle and the new level
+  pub fn take_toggles(&self) -> Vec<(u64, bool)> {
+    self.toggles.borrow_mut().drain(..).collect()
+  }
+
+  // The speaker's output at the given cycle, from -1.0 to 1.0, consuming the
+  // changes up to it. Meant to be called from System::on_sample, with the
+  // sample's cycle, in place of take_toggles.
+  pub fn sample(&self, cycle: u64) -> f32 {
+    let mut toggles = self.toggles.borrow_mut();
+
+    while let Some(&(at, level)) = toggles.front() {
+      if at > cycle {
+        break;
+      }
+      toggles.pop_front();
+      self.sampled.set(level);
+    }
+
+    match self.sampled.get() {
+      true => 1.0,
+      false => -1.0,
+    }
+  }
+}
+
+impl Memory for SpeakerMemory {
+  fn read(&self, _address: u16) -> u8 {
+    self.level.get() as u8
+  }
+
+  fn write(&mut self, _address: u16, value: u8) {
+    let level = value & 1 != 0;
+
+    if level != self.level.get() {
+      self.level.set(level);
+      self
+        .toggles
+        .borrow_mut()
+        .push_back((self.clock.get(), level));
+    }
+  }
+
+  fn tick(&mut self) {}
+
+  fn reset(&mut self) {
+    self.level.set(false);
+    self.sampled.set(false);
+    self.toggles.borrow_mut().clear();
+  }
+}
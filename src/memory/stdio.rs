@@ -19,7 +19,7 @@ impl Memory for MappedStdIO {
         let char = input.chars().next().expect("String is empty");
         ((char as u32) & 0xFF) as u8
       }
-      0x02 => u8::from_str_radix(&input.trim(), 16).expect("Invalid input for u8"),
+      0x02 => u8::from_str_radix(input.trim(), 16).expect("Invalid input for u8"),
       _ => unreachable!(),
     }
   }
@@ -40,6 +40,11 @@ impl Memory for MappedStdIO {
   fn tick(&mut self) {}
 
   fn reset(&mut self) {}
+
+  // Reads block on stdin, so there's nothing to see without one
+  fn peek(&self, _address: u16) -> u8 {
+    0
+  }
 }
 
 impl MappedStdIO {
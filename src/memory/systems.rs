@@ -1,10 +1,17 @@
+#[cfg(feature = "graphics")]
 use crate::graphics::GraphicsProvider;
+use crate::input::InputProvider;
 use crate::memory::{
-  easy::{EasyIO, EasyVram},
+  easy::EasyIO, is_hex_file, BlockMemory, BranchMemory, MappedStdIO, Memory, NullMemory, TrapMemory,
+};
+#[cfg(feature = "graphics")]
+use crate::memory::{
+  easy::EasyVram,
   pet::{PetIO, PetVram},
-  BlockMemory, BranchMemory, MappedStdIO, Memory, NullMemory,
 };
+#[cfg(feature = "graphics")]
 use std::cell::RefCell;
+#[cfg(feature = "graphics")]
 use std::rc::Rc;
 
 pub enum Mapping {
@@ -13,74 +20,141 @@ pub enum Mapping {
   CommodorePET,
 }
 
+// Raw binaries are loaded at the start of the block, while hex images place
+// their data at absolute addresses
+fn load_rom(size: usize, address: usize, path: &str) -> Result<BlockMemory, String> {
+  match is_hex_file(path) {
+    true => BlockMemory::from_hex(size, address, path),
+    false => BlockMemory::from_file(size, path),
+  }
+}
+
 pub fn create_memory(
   mapping: Mapping,
-  graphics: Option<Box<dyn GraphicsProvider>>,
+  #[cfg(feature = "graphics")] graphics: Option<Rc<RefCell<Box<dyn GraphicsProvider>>>>,
+  input: Option<Box<dyn InputProvider>>,
   rom: &str,
-) -> Box<dyn Memory> {
+) -> Result<Box<dyn Memory>, String> {
   match mapping {
     Mapping::BrookeSystem => {
       let ram = BlockMemory::ram(0x4000);
       let io = MappedStdIO::new();
-      let rom = BlockMemory::from_file(0x8000, rom);
+      let rom = load_rom(0x8000, 0x8000, rom)?;
 
       let memory = BranchMemory::new()
         .map(0x0000, Box::new(ram))
+        .label("RAM")
         .map(0x4000, Box::new(io))
-        .map(0x8000, Box::new(rom));
+        .label("Stdio")
+        .map_readonly(0x8000, 0x8000, Box::new(rom))
+        .label("ROM");
 
-      Box::new(memory)
+      Ok(Box::new(memory))
     }
     Mapping::Easy6502 => {
-      let graphics = Rc::new(RefCell::new(graphics.unwrap()));
-
       let zero_page = BlockMemory::ram(0x0100);
-      let io = EasyIO::new(Rc::clone(&graphics));
+      let io = EasyIO::new(input.unwrap());
       let stack_ram = BlockMemory::ram(0x0100);
-      let vram = EasyVram::new(32, 32, graphics);
+      #[cfg(feature = "graphics")]
+      let vram = EasyVram::new(32, 32, graphics.unwrap());
+      #[cfg(not(feature = "graphics"))]
+      let vram = NullMemory::new(); // nowhere to draw the screen
       let high_ram = BlockMemory::ram(0x7A00);
-      let rom = BlockMemory::from_file(0x8000, rom);
+      let rom = load_rom(0x8000, 0x8000, rom)?;
 
       let memory = BranchMemory::new()
         .map(0x0000, Box::new(zero_page))
+        .label("Zero page")
         .map(0x00fe, Box::new(io))
+        .label("IO")
         .map(0x0100, Box::new(stack_ram))
+        .label("Stack")
         .map(0x0200, Box::new(vram))
+        .label("VRAM")
         .map(0x0600, Box::new(high_ram))
-        .map(0x8000, Box::new(rom));
+        .label("RAM")
+        .map_readonly(0x8000, 0x8000, Box::new(rom))
+        .label("ROM");
 
-      Box::new(memory)
+      Ok(Box::new(memory))
     }
+    #[cfg(not(feature = "graphics"))]
+    Mapping::CommodorePET => Err("The PET needs the graphics feature for its screen".to_string()),
+    #[cfg(feature = "graphics")]
     Mapping::CommodorePET => {
-      let graphics = Rc::new(RefCell::new(graphics.unwrap()));
-
       let ram = BlockMemory::ram(0x8000);
-      let vram = PetVram::new("bin/pet_char.bin", Rc::clone(&graphics));
+      let vram = PetVram::new("bin/pet_char.bin", graphics.unwrap())?;
 
       let expansion_rom_9 = NullMemory::new();
       let expansion_rom_a = NullMemory::new();
       let expansion_rom_b = NullMemory::new();
 
-      let basic_rom = BlockMemory::from_file(0x8000, "bin/pet_basic.bin");
+      let basic_rom = BlockMemory::from_file(0x8000, "bin/pet_basic.bin")?;
 
-      let editor_rom = BlockMemory::from_file(0x1000, "bin/pet_editor.bin");
+      let editor_rom = BlockMemory::from_file(0x1000, "bin/pet_editor.bin")?;
 
       let io = PetIO::new();
 
-      let kernel_rom = BlockMemory::from_file(0x1000, "bin/pet_kernal.bin"); // TODO: actual kernel
+      let kernel_rom = BlockMemory::from_file(0x1000, "bin/pet_kernal.bin")?; // TODO: actual kernel
 
       let memory = BranchMemory::new()
         .map(0x0000, Box::new(ram))
+        .label("RAM")
         .map(0x8000, Box::new(vram))
+        .label("VRAM")
         .map(0x9000, Box::new(expansion_rom_9))
+        .label("Expansion ROM")
         .map(0xA000, Box::new(expansion_rom_a))
+        .label("Expansion ROM")
         .map(0xB000, Box::new(expansion_rom_b))
-        .map(0xC000, Box::new(basic_rom))
-        .map(0xE000, Box::new(editor_rom))
+        .label("Expansion ROM")
+        .map_readonly(0xC000, 0x2000, Box::new(basic_rom))
+        .label("BASIC ROM")
+        .map_readonly(0xE000, 0x0800, Box::new(editor_rom))
+        .label("Editor ROM")
         .map(0xE800, Box::new(io))
-        .map(0xF000, Box::new(kernel_rom));
+        .label("IO")
+        .map_readonly(0xF000, 0x1000, Box::new(kernel_rom))
+        .label("Kernal ROM");
 
-      Box::new(memory)
+      Ok(Box::new(memory))
     }
   }
 }
+
+// For bringing up a new ROM: the ROM at the top of memory and a little RAM at
+// the bottom (at least the zero page and stack), with everything in between
+// unmapped. The first write there is reported, and the returned TrapMemory
+// can be asked where it was.
+pub fn rom_bringup(rom: &[u8], ram_size: usize) -> Result<(Box<dyn Memory>, TrapMemory), String> {
+  if ram_size < 0x200 {
+    return Err("The RAM has to cover the zero page and stack".to_string());
+  }
+
+  if rom.is_empty() || ram_size + rom.len() > 0x10000 {
+    return Err(format!(
+      "A {} byte ROM doesn't fit above {} bytes of RAM",
+      rom.len(),
+      ram_size
+    ));
+  }
+
+  let rom_start = 0x10000 - rom.len();
+  let trap = TrapMemory::new(ram_size as u16);
+
+  let mut memory = BranchMemory::new()
+    .map(0x0000, Box::new(BlockMemory::ram(ram_size)))
+    .label("RAM");
+
+  if rom_start > ram_size {
+    memory = memory
+      .map(ram_size, Box::new(trap.clone()))
+      .label("Unmapped");
+  }
+
+  let memory = memory
+    .map_readonly(rom_start, rom.len(), Box::new(BlockMemory::from_bytes(rom)))
+    .label("ROM");
+
+  Ok((Box::new(memory), trap))
+}
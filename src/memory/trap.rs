@@ -0,0 +1,62 @@
+use crate::memory::{BusError, Memory};
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Fills a hole in the memory map, reporting the first write into it, which
+// is usually the first sign of a ROM expecting hardware that isn't there.
+// Reads return 0, and are bus errors in strict mode. The write is recorded in
+// one place for all clones, so first_write can be asked of any of them.
+#[derive(Clone)]
+pub struct TrapMemory {
+  base: u16, // where the hole starts, to report absolute addresses
+  first_write: Rc<Cell<Option<(u16, u8)>>>,
+}
+
+impl TrapMemory {
+  pub fn new(base: u16) -> Self {
+    Self {
+      base,
+      first_write: Rc::new(Cell::new(None)),
+    }
+  }
+
+  // The address and value of the first write, if there's been one
+  pub fn first_write(&self) -> Option<(u16, u8)> {
+    self.first_write.get()
+  }
+}
+
+impl Memory for TrapMemory {
+  fn read(&self, _address: u16) -> u8 {
+    0
+  }
+
+  fn write(&mut self, address: u16, value: u8) {
+    if self.first_write.get().is_none() {
+      let address = self.base.wrapping_add(address);
+      println!("Stray write of {:02X} to {:04X}", value, address);
+      self.first_write.set(Some((address, value)));
+    }
+  }
+
+  fn tick(&mut self) {}
+
+  fn reset(&mut self) {}
+
+  fn try_read(&self, address: u16) -> Result<u8, BusError> {
+    Err(BusError {
+      address: self.base.wrapping_add(address),
+    })
+  }
+
+  fn try_write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+    self.write(address, value);
+    Err(BusError {
+      address: self.base.wrapping_add(address),
+    })
+  }
+
+  fn mapped(&self, _address: u16) -> bool {
+    false
+  }
+}
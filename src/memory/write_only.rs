@@ -0,0 +1,54 @@
+use crate::memory::{BusError, Memory};
+use std::collections::HashSet;
+
+// Wraps a device so that some of its registers can only be written, with
+// reads from them returning a fixed value (e.g. the open bus) instead of
+// whatever was last written
+pub struct WriteOnlyMemory {
+  memory: Box<dyn Memory>,
+  registers: HashSet<u16>,
+  value: u8,
+}
+
+impl WriteOnlyMemory {
+  pub fn new(memory: Box<dyn Memory>, value: u8) -> Self {
+    Self {
+      memory,
+      registers: HashSet::new(),
+      value,
+    }
+  }
+
+  // Mark the register at the given address within the device as write-only
+  pub fn register(mut self, address: u16) -> Self {
+    self.registers.insert(address);
+    self
+  }
+}
+
+impl Memory for WriteOnlyMemory {
+  fn read(&self, address: u16) -> u8 {
+    match self.registers.contains(&address) {
+      true => self.value,
+      false => self.memory.read(address),
+    }
+  }
+
+  fn try_read(&self, address: u16) -> Result<u8, BusError> {
+    match self.registers.contains(&address) {
+      true => Ok(self.value),
+      false => self.memory.try_read(address),
+    }
+  }
+
+  fn peek(&self, address: u16) -> u8 {
+    match self.registers.contains(&address) {
+      true => self.value,
+      false => self.memory.peek(address),
+    }
+  }
+
+  forward_memory!(self => self.memory, self.memory;
+    write, tick, reset, try_write, mapped, wait_states, next_event, reload, save_state,
+    load_state, map_device, unmap_device);
+}
@@ -0,0 +1,284 @@
+// Mnemonic and addressing mode for each implemented opcode
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+  Implied,
+  Accumulator,
+  Immediate,
+  ZeroPage,
+  ZeroPageX,
+  ZeroPageY,
+  Absolute,
+  AbsoluteX,
+  AbsoluteY,
+  Indirect,
+  IndirectX,
+  IndirectY,
+  Relative,
+}
+
+impl Mode {
+  // Length of the whole instruction in bytes, including the opcode
+  pub fn length(self) -> u16 {
+    match self {
+      Mode::Implied | Mode::Accumulator => 1,
+      Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3,
+      _ => 2,
+    }
+  }
+}
+
+pub fn decode(opcode: u8) -> Option<(&'static str, Mode)> {
+  use Mode::*;
+
+  let decoded = match opcode {
+    0x00 => ("BRK", Implied),
+    0x01 => ("ORA", IndirectX),
+    0x05 => ("ORA", ZeroPage),
+    0x06 => ("ASL", ZeroPage),
+    0x08 => ("PHP", Implied),
+    0x09 => ("ORA", Immediate),
+    0x0A => ("ASL", Accumulator),
+    0x0D => ("ORA", Absolute),
+    0x0E => ("ASL", Absolute),
+    0x10 => ("BPL", Relative),
+    0x11 => ("ORA", IndirectY),
+    0x15 => ("ORA", ZeroPageX),
+    0x16 => ("ASL", ZeroPageX),
+    0x18 => ("CLC", Implied),
+    0x19 => ("ORA", AbsoluteY),
+    0x1D => ("ORA", AbsoluteX),
+    0x1E => ("ASL", AbsoluteX),
+    0x20 => ("JSR", Absolute),
+    0x21 => ("AND", IndirectX),
+    0x24 => ("BIT", ZeroPage),
+    0x25 => ("AND", ZeroPage),
+    0x26 => ("ROL", ZeroPage),
+    0x28 => ("PLP", Implied),
+    0x29 => ("AND", Immediate),
+    0x2A => ("ROL", Accumulator),
+    0x2C => ("BIT", Absolute),
+    0x2D => ("AND", Absolute),
+    0x2E => ("ROL", Absolute),
+    0x30 => ("BMI", Relative),
+    0x31 => ("AND", IndirectY),
+    0x35 => ("AND", ZeroPageX),
+    0x36 => ("ROL", ZeroPageX),
+    0x38 => ("SEC", Implied),
+    0x39 => ("AND", AbsoluteY),
+    0x3D => ("AND", AbsoluteX),
+    0x3E => ("ROL", AbsoluteX),
+    0x40 => ("RTI", Implied),
+    0x41 => ("EOR", IndirectX),
+    0x45 => ("EOR", ZeroPage),
+    0x46 => ("LSR", ZeroPage),
+    0x48 => ("PHA", Implied),
+    0x49 => ("EOR", Immediate),
+    0x4A => ("LSR", Accumulator),
+    0x4C => ("JMP", Absolute),
+    0x4D => ("EOR", Absolute),
+    0x4E => ("LSR", Absolute),
+    0x50 => ("BVC", Relative),
+    0x51 => ("EOR", IndirectY),
+    0x55 => ("EOR", ZeroPageX),
+    0x56 => ("LSR", ZeroPageX),
+    0x58 => ("CLI", Implied),
+    0x59 => ("EOR", AbsoluteY),
+    0x5D => ("EOR", AbsoluteX),
+    0x5E => ("LSR", AbsoluteX),
+    0x60 => ("RTS", Implied),
+    0x61 => ("ADC", IndirectX),
+    0x65 => ("ADC", ZeroPage),
+    0x66 => ("ROR", ZeroPage),
+    0x68 => ("PLA", Implied),
+    0x69 => ("ADC", Immediate),
+    0x6A => ("ROR", Accumulator),
+    0x6C => ("JMP", Indirect),
+    0x6D => ("ADC", Absolute),
+    0x6E => ("ROR", Absolute),
+    0x70 => ("BVS", Relative),
+    0x71 => ("ADC", IndirectY),
+    0x75 => ("ADC", ZeroPageX),
+    0x76 => ("ROR", ZeroPageX),
+    0x78 => ("SEI", Implied),
+    0x79 => ("ADC", AbsoluteY),
+    0x7D => ("ADC", AbsoluteX),
+    0x7E => ("ROR", AbsoluteX),
+    0x81 => ("STA", IndirectX),
+    0x84 => ("STY", ZeroPage),
+    0x85 => ("STA", ZeroPage),
+    0x86 => ("STX", ZeroPage),
+    0x88 => ("DEY", Implied),
+    0x8A => ("TXA", Implied),
+    0x8C => ("STY", Absolute),
+    0x8D => ("STA", Absolute),
+    0x8E => ("STX", Absolute),
+    0x90 => ("BCC", Relative),
+    0x91 => ("STA", IndirectY),
+    0x94 => ("STY", ZeroPageX),
+    0x95 => ("STA", ZeroPageX),
+    0x96 => ("STX", ZeroPageY),
+    0x98 => ("TYA", Implied),
+    0x99 => ("STA", AbsoluteY),
+    0x9A => ("TXS", Implied),
+    0x9D => ("STA", AbsoluteX),
+    0xA0 => ("LDY", Immediate),
+    0xA1 => ("LDA", IndirectX),
+    0xA2 => ("LDX", Immediate),
+    0xA4 => ("LDY", ZeroPage),
+    0xA5 => ("LDA", ZeroPage),
+    0xA6 => ("LDX", ZeroPage),
+    0xA8 => ("TAY", Implied),
+    0xA9 => ("LDA", Immediate),
+    0xAA => ("TAX", Implied),
+    0xAC => ("LDY", Absolute),
+    0xAD => ("LDA", Absolute),
+    0xAE => ("LDX", Absolute),
+    0xB0 => ("BCS", Relative),
+    0xB1 => ("LDA", IndirectY),
+    0xB4 => ("LDY", ZeroPageX),
+    0xB5 => ("LDA", ZeroPageX),
+    0xB6 => ("LDX", ZeroPageY),
+    0xB8 => ("CLV", Implied),
+    0xB9 => ("LDA", AbsoluteY),
+    0xBA => ("TSX", Implied),
+    0xBC => ("LDY", AbsoluteX),
+    0xBD => ("LDA", AbsoluteX),
+    0xBE => ("LDX", AbsoluteY),
+    0xC0 => ("CPY", Immediate),
+    0xC1 => ("CMP", IndirectX),
+    0xC4 => ("CPY", ZeroPage),
+    0xC5 => ("CMP", ZeroPage),
+    0xC6 => ("DEC", ZeroPage),
+    0xC8 => ("INY", Implied),
+    0xC9 => ("CMP", Immediate),
+    0xCA => ("DEX", Implied),
+    0xCC => ("CPY", Absolute),
+    0xCD => ("CMP", Absolute),
+    0xCE => ("DEC", Absolute),
+    0xD0 => ("BNE", Relative),
+    0xD1 => ("CMP", IndirectY),
+    0xD5 => ("CMP", ZeroPageX),
+    0xD6 => ("DEC", ZeroPageX),
+    0xD8 => ("CLD", Implied),
+    0xD9 => ("CMP", AbsoluteY),
+    0xDD => ("CMP", AbsoluteX),
+    0xDE => ("DEC", AbsoluteX),
+    0xE0 => ("CPX", Immediate),
+    0xE1 => ("SBC", IndirectX),
+    0xE4 => ("CPX", ZeroPage),
+    0xE5 => ("SBC", ZeroPage),
+    0xE6 => ("INC", ZeroPage),
+    0xE8 => ("INX", Implied),
+    0xE9 => ("SBC", Immediate),
+    0xEA => ("NOP", Implied),
+    0xEC => ("CPX", Absolute),
+    0xED => ("SBC", Absolute),
+    0xEE => ("INC", Absolute),
+    0xF0 => ("BEQ", Relative),
+    0xF1 => ("SBC", IndirectY),
+    0xF5 => ("SBC", ZeroPageX),
+    0xF6 => ("INC", ZeroPageX),
+    0xF8 => ("SED", Implied),
+    0xF9 => ("SBC", AbsoluteY),
+    0xFD => ("SBC", AbsoluteX),
+    0xFE => ("INC", AbsoluteX),
+    _ => return None,
+  };
+
+  Some(decoded)
+}
+
+// The addressing modes each instruction has, from the 6502 reference
+#[cfg(feature = "validate")]
+fn legal_modes(mnemonic: &str) -> &'static [Mode] {
+  use Mode::*;
+
+  match mnemonic {
+    "ADC" | "AND" | "CMP" | "EOR" | "LDA" | "ORA" | "SBC" => &[
+      Immediate, ZeroPage, ZeroPageX, Absolute, AbsoluteX, AbsoluteY, IndirectX, IndirectY,
+    ],
+    "STA" => &[
+      ZeroPage, ZeroPageX, Absolute, AbsoluteX, AbsoluteY, IndirectX, IndirectY,
+    ],
+    "ASL" | "LSR" | "ROL" | "ROR" => &[Accumulator, ZeroPage, ZeroPageX, Absolute, AbsoluteX],
+    "DEC" | "INC" => &[ZeroPage, ZeroPageX, Absolute, AbsoluteX],
+    "LDX" => &[Immediate, ZeroPage, ZeroPageY, Absolute, AbsoluteY],
+    "LDY" => &[Immediate, ZeroPage, ZeroPageX, Absolute, AbsoluteX],
+    "STX" => &[ZeroPage, ZeroPageY, Absolute],
+    "STY" => &[ZeroPage, ZeroPageX, Absolute],
+    "CPX" | "CPY" => &[Immediate, ZeroPage, Absolute],
+    "BIT" => &[ZeroPage, Absolute],
+    "JMP" => &[Absolute, Indirect],
+    "JSR" => &[Absolute],
+    "BCC" | "BCS" | "BEQ" | "BMI" | "BNE" | "BPL" | "BVC" | "BVS" => &[Relative],
+    "BRK" | "CLC" | "CLD" | "CLI" | "CLV" | "DEX" | "DEY" | "INX" | "INY" | "NOP" | "PHA"
+    | "PHP" | "PLA" | "PLP" | "RTI" | "RTS" | "SEC" | "SED" | "SEI" | "TAX" | "TAY" | "TSX"
+    | "TXA" | "TXS" | "TYA" => &[Implied],
+    _ => &[],
+  }
+}
+
+// Check decode against the reference: every opcode has to use one of its
+// instruction's modes, and every mode of every instruction has to be decoded
+// from exactly one opcode, so a typo in the table can't go unnoticed
+#[cfg(feature = "validate")]
+pub fn check_decode_table() {
+  let decoded: Vec<(u8, &str, Mode)> = (0..=255)
+    .filter_map(|opcode| decode(opcode).map(|(mnemonic, mode)| (opcode, mnemonic, mode)))
+    .collect();
+
+  for &(opcode, mnemonic, mode) in &decoded {
+    assert!(
+      legal_modes(mnemonic).contains(&mode),
+      "Opcode {:02X} decodes to {} with {:?}, which it doesn't have",
+      opcode,
+      mnemonic,
+      mode
+    );
+  }
+
+  for &(_, mnemonic, _) in &decoded {
+    for &mode in legal_modes(mnemonic) {
+      let count = decoded
+        .iter()
+        .filter(|&&(_, other, other_mode)| other == mnemonic && other_mode == mode)
+        .count();
+      assert!(
+        count == 1,
+        "{} with {:?} is decoded from {} opcodes rather than one",
+        mnemonic,
+        mode,
+        count
+      );
+    }
+  }
+}
+
+// Undocumented opcodes that lock up the CPU until it's reset
+pub fn jams(opcode: u8) -> bool {
+  match opcode & 0x1F {
+    0x12 => true,
+    0x02 => !matches!(opcode, 0x82 | 0xA2 | 0xC2 | 0xE2),
+    _ => false,
+  }
+}
+
+// Instruction length in bytes for any opcode, including the undocumented
+// ones, which follow the addressing modes of their columns
+pub fn length(opcode: u8) -> u16 {
+  if let Some((_, mode)) = decode(opcode) {
+    return mode.length();
+  }
+
+  match opcode & 0x1F {
+    // Immediate NOPs, everything else in these columns jams the CPU
+    0x00 | 0x02 => match opcode {
+      0x80 | 0x82 | 0xC2 | 0xE2 => 2,
+      _ => 1,
+    },
+    0x12 | 0x0A | 0x1A => 1,
+    0x09 | 0x0B | 0x03 | 0x13 | 0x04 | 0x14 | 0x07 | 0x17 => 2,
+    _ => 3,
+  }
+}
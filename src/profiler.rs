@@ -0,0 +1,67 @@
+use crate::system::System;
+use std::collections::HashMap;
+
+// Where the emulated program spends its time, for flame graphs. Every so
+// many cycles it takes the call stack (see System::call_stack, so it's only
+// as good as that guess) and charges the cycles since the last sample to it.
+// Subroutines are named by their label, or their address without one.
+pub struct CallProfiler {
+  interval: u64,
+  last_sample: u64,
+  stacks: HashMap<Vec<u16>, u64>, // entry points, outermost first -> cycles
+}
+
+impl CallProfiler {
+  pub fn new(interval: u64) -> Self {
+    Self {
+      interval: interval.max(1),
+      last_sample: 0,
+      stacks: HashMap::new(),
+    }
+  }
+
+  // Call after every step
+  pub fn sample(&mut self, system: &System) {
+    let cycles = system.cycles();
+    if cycles < self.last_sample + self.interval {
+      return;
+    }
+
+    // Each JSR's operand is the routine it called
+    let stack = system
+      .call_stack()
+      .into_iter()
+      .rev()
+      .map(|jsr| {
+        let lo = system.peek(jsr.wrapping_add(1));
+        let hi = system.peek(jsr.wrapping_add(2));
+        (hi as u16) << 8 | lo as u16
+      })
+      .collect();
+
+    *self.stacks.entry(stack).or_insert(0) += cycles - self.last_sample;
+    self.last_sample = cycles;
+  }
+
+  // The samples in the folded format that flame graph tools read: one line
+  // per call path, from the outermost routine in, with its cycle count
+  pub fn folded(&self, system: &System) -> String {
+    let mut lines: Vec<String> = self
+      .stacks
+      .iter()
+      .map(|(stack, cycles)| {
+        let names: Vec<String> = std::iter::once("main".to_string())
+          .chain(stack.iter().map(|&address| match system.label_at(address) {
+            Some(label) => label.to_string(),
+            None => format!("${:04X}", address),
+          }))
+          .collect();
+
+        format!("{} {}", names.join(";"), cycles)
+      })
+      .collect();
+
+    lines.sort();
+    lines.join("\n") + "\n"
+  }
+}
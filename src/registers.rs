@@ -1,3 +1,6 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Registers {
   pub a: u8,
   pub x: u8,
@@ -12,12 +15,13 @@ pub mod flags {
   pub const ZERO: u8 = 0b00000010;
   pub const INTERRUPT: u8 = 0b00000100;
   pub const DECIMAL: u8 = 0b00001000;
-  pub const _BREAK: u8 = 0b00010000;
-  pub const _UNUSED: u8 = 0b00100000;
+  pub const BREAK: u8 = 0b00010000;
+  pub const UNUSED: u8 = 0b00100000;
   pub const OVERFLOW: u8 = 0b01000000;
   pub const NEGATIVE: u8 = 0b10000000;
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct StackPointer {
   value: u8,
 }
@@ -42,12 +46,9 @@ impl StackPointer {
   pub fn set(&mut self, value: u8) {
     self.value = value;
   }
-
-  pub fn address(&self) -> u16 {
-    0x0100 + self.value as u16
-  }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct ProgramCounter {
   value: u16,
 }
@@ -62,7 +63,7 @@ impl ProgramCounter {
   }
 
   pub fn increment(&mut self) {
-    self.value += 1;
+    self.value = self.value.wrapping_add(1);
   }
 
   pub fn load(&mut self, address: u16) {
@@ -74,6 +75,7 @@ impl ProgramCounter {
   }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct StatusRegister {
   value: u8,
 }
@@ -115,9 +117,90 @@ impl StatusRegister {
     self.write(flags::NEGATIVE, value & 0x80 != 0);
     self.write(flags::ZERO, value == 0);
   }
+
+  // B and the unused bit aren't real flags, they only exist on the stack. The
+  // unused bit is always pushed set, and B tells a handler whether it was
+  // entered by BRK or PHP (set) rather than an IRQ or NMI (clear). Every push
+  // of the status goes through here, and every pull through from_pulled.
+  pub fn pushed_value(&self, b_set: bool) -> u8 {
+    match b_set {
+      true => self.value | flags::BREAK | flags::UNUSED,
+      false => (self.value & !flags::BREAK) | flags::UNUSED,
+    }
+  }
+
+  // The status pulled by PLP or RTI, ignoring whatever B and the unused bit
+  // were on the stack
+  pub fn from_pulled(value: u8) -> Self {
+    Self {
+      value: value | flags::BREAK | flags::UNUSED,
+    }
+  }
+
+  // The names these went by before, kept for existing callers
+  #[deprecated(note = "use pushed_value")]
+  pub fn to_u8(&self, brk: bool) -> u8 {
+    self.pushed_value(brk)
+  }
+
+  #[deprecated(note = "use from_pulled")]
+  pub fn from_u8(value: u8) -> Self {
+    Self::from_pulled(value)
+  }
+
+  pub fn carry(&self) -> bool {
+    self.read(flags::CARRY)
+  }
+
+  pub fn set_carry(&mut self, value: bool) {
+    self.write(flags::CARRY, value);
+  }
+
+  pub fn zero(&self) -> bool {
+    self.read(flags::ZERO)
+  }
+
+  pub fn set_zero(&mut self, value: bool) {
+    self.write(flags::ZERO, value);
+  }
+
+  pub fn interrupt_disable(&self) -> bool {
+    self.read(flags::INTERRUPT)
+  }
+
+  pub fn set_interrupt_disable(&mut self, value: bool) {
+    self.write(flags::INTERRUPT, value);
+  }
+
+  pub fn decimal(&self) -> bool {
+    self.read(flags::DECIMAL)
+  }
+
+  pub fn set_decimal(&mut self, value: bool) {
+    self.write(flags::DECIMAL, value);
+  }
+
+  pub fn overflow(&self) -> bool {
+    self.read(flags::OVERFLOW)
+  }
+
+  pub fn set_overflow(&mut self, value: bool) {
+    self.write(flags::OVERFLOW, value);
+  }
+
+  pub fn negative(&self) -> bool {
+    self.read(flags::NEGATIVE)
+  }
+
+  pub fn set_negative(&mut self, value: bool) {
+    self.write(flags::NEGATIVE, value);
+  }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub trait ALU {
+  // Both read the carry flag as their carry in (for subtraction, a clear
+  // carry means borrow), so the ADC and SBC handlers don't pass it along
   fn alu_add(&mut self, value: u8);
   fn alu_subtract(&mut self, value: u8);
   fn alu_compare(&mut self, register: u8, value: u8);
@@ -125,6 +208,31 @@ pub trait ALU {
 
 impl ALU for Registers {
   fn alu_add(&mut self, value: u8) {
+    if self.sr.read(flags::DECIMAL) {
+      self.decimal_add(value);
+    } else {
+      self.binary_add(value);
+    }
+  }
+
+  fn alu_subtract(&mut self, value: u8) {
+    if self.sr.read(flags::DECIMAL) {
+      self.decimal_subtract(value);
+    } else {
+      self.binary_add(!value);
+    }
+  }
+
+  fn alu_compare(&mut self, register: u8, value: u8) {
+    self.sr.write(flags::CARRY, register >= value);
+    self.sr.write(flags::ZERO, register == value);
+    let negative = register.wrapping_sub(value) & 0x80 != 0;
+    self.sr.write(flags::NEGATIVE, negative);
+  }
+}
+
+impl Registers {
+  fn binary_add(&mut self, value: u8) {
     let sum = (self.a as u16)
       .wrapping_add(value as u16)
       .wrapping_add(self.sr.read(flags::CARRY) as u16);
@@ -139,19 +247,53 @@ impl ALU for Registers {
     self.sr.set_nz(self.a);
   }
 
-  fn alu_subtract(&mut self, value: u8) {
-    self.alu_add(!value);
+  // On the NMOS 6502, decimal ADC sets Z from the binary sum, and N and V
+  // from the result before the high nibble is corrected.
+  // (see http://www.6502.org/tutorials/decimal_mode.html)
+  fn decimal_add(&mut self, value: u8) {
+    let carry = self.sr.read(flags::CARRY) as u16;
+    let binary = (self.a as u16) + (value as u16) + carry;
+
+    let mut lo = (self.a & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+    if lo >= 0x0A {
+      lo = ((lo + 0x06) & 0x0F) + 0x10;
+    }
+
+    let mut result = (self.a & 0xF0) as u16 + (value & 0xF0) as u16 + lo;
+
+    self.sr.write(flags::ZERO, binary & 0xFF == 0);
+    self.sr.write(flags::NEGATIVE, result & 0x80 != 0);
+    self.sr.write(
+      flags::OVERFLOW,
+      !(self.a ^ value) & (self.a ^ result as u8) & 0x80 != 0,
+    );
+
+    if result >= 0xA0 {
+      result += 0x60;
+    }
+
+    self.sr.write(flags::CARRY, result > 0xFF);
+    self.a = result as u8;
   }
 
-  fn alu_compare(&mut self, register: u8, value: u8) {
-    self.sr.write(flags::CARRY, register >= value);
-    self.sr.write(flags::ZERO, register == value);
-    let negative = register.wrapping_sub(value) & 0x80 != 0;
-    self.sr.write(flags::NEGATIVE, negative);
+  // On the NMOS 6502, decimal SBC sets every flag as binary SBC would
+  fn decimal_subtract(&mut self, value: u8) {
+    let borrow = !self.sr.read(flags::CARRY) as i16;
+
+    let mut lo = (self.a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+    if lo < 0 {
+      lo = ((lo - 0x06) & 0x0F) - 0x10;
+    }
+
+    let mut result = (self.a & 0xF0) as i16 - (value & 0xF0) as i16 + lo;
+    if result < 0 {
+      result -= 0x60;
+    }
+
+    self.binary_add(!value);
+    self.a = result as u8;
   }
-}
 
-impl Registers {
   pub fn new() -> Registers {
     Registers {
       a: 0,
@@ -172,3 +314,118 @@ impl Registers {
     self.sr = StatusRegister::new();
   }
 }
+
+// The registers as plain values, for setting up a System in a given state
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RegisterSnapshot {
+  pub a: u8,
+  pub x: u8,
+  pub y: u8,
+  pub sp: u8,
+  pub pc: u16,
+  pub sr: u8,
+}
+
+impl Registers {
+  pub fn snapshot(&self) -> RegisterSnapshot {
+    RegisterSnapshot {
+      a: self.a,
+      x: self.x,
+      y: self.y,
+      sp: self.sp.get(),
+      pc: self.pc.address(),
+      sr: self.sr.get(),
+    }
+  }
+
+  pub fn restore(&mut self, snapshot: RegisterSnapshot) {
+    self.a = snapshot.a;
+    self.x = snapshot.x;
+    self.y = snapshot.y;
+    self.sp.set(snapshot.sp);
+    self.pc.load(snapshot.pc);
+    self.sr.load(snapshot.sr);
+  }
+
+  // Each register that differs between the two, with both values
+  pub fn diff(&self, other: &Registers) -> Vec<(&'static str, u16, u16)> {
+    [
+      ("PC", self.pc.address(), other.pc.address()),
+      ("A", self.a as u16, other.a as u16),
+      ("X", self.x as u16, other.x as u16),
+      ("Y", self.y as u16, other.y as u16),
+      ("SP", self.sp.get() as u16, other.sp.get() as u16),
+      ("SR", self.sr.get() as u16, other.sr.get() as u16),
+    ]
+    .into_iter()
+    .filter(|&(_, a, b)| a != b)
+    .collect()
+  }
+}
+
+impl fmt::Display for Registers {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "PC={:04X} A={:02X} X={:02X} Y={:02X} SP={:02X} SR={:08b}",
+      self.pc.address(),
+      self.a,
+      self.x,
+      self.y,
+      self.sp.get(),
+      self.sr.get()
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decimal_arithmetic_matches_the_nmos_6502() {
+    // a, operand, subtract, result, then the N, V, Z and C flags
+    let cases = [
+      (0x99, 0x01, false, 0x00, [true, false, false, true]),
+      (0x50, 0x50, false, 0x00, [true, true, false, true]),
+      (0x12, 0x34, false, 0x46, [false, false, false, false]),
+      (0x00, 0x01, true, 0x99, [true, false, false, false]),
+      (0x46, 0x12, true, 0x34, [false, false, false, true]),
+    ];
+
+    for (a, value, subtract, result, [n, v, z, c]) in cases {
+      let mut registers = Registers::new();
+      registers.a = a;
+      registers.sr.set(flags::DECIMAL);
+      // No carry in for ADC, no borrow for SBC
+      registers.sr.write(flags::CARRY, subtract);
+
+      match subtract {
+        false => registers.alu_add(value),
+        true => registers.alu_subtract(value),
+      }
+
+      let case = format!(
+        "{:02X} {} {:02X}",
+        a,
+        if subtract { '-' } else { '+' },
+        value
+      );
+      assert_eq!(registers.a, result, "{}", case);
+      assert_eq!(registers.sr.read(flags::NEGATIVE), n, "N after {}", case);
+      assert_eq!(registers.sr.read(flags::OVERFLOW), v, "V after {}", case);
+      assert_eq!(registers.sr.read(flags::ZERO), z, "Z after {}", case);
+      assert_eq!(registers.sr.read(flags::CARRY), c, "C after {}", case);
+    }
+  }
+
+  #[test]
+  #[allow(deprecated)]
+  fn old_status_conversions_match_the_new_ones() {
+    let status = StatusRegister::from_pulled(0x81);
+    for brk in [false, true] {
+      assert_eq!(status.to_u8(brk), status.pushed_value(brk));
+    }
+    assert_eq!(StatusRegister::from_u8(0x81), status);
+  }
+}
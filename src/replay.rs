@@ -0,0 +1,149 @@
+use crate::input::{InputProvider, KeyEvent, QueueInputProvider};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::rc::Rc;
+
+// External events that affect a run, so that it can be reproduced exactly
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+  Key(KeyEvent),
+  Irq,
+  Nmi,
+}
+
+// Events keyed by the cycle count at which they were delivered. Key events
+// from the live input provider are routed through here on their way to the
+// memory-mapped devices, so they can be logged alongside interrupts.
+pub struct EventQueue {
+  source: Option<Box<dyn InputProvider>>,
+  scheduled: VecDeque<(u64, Event)>,
+  keys: Rc<RefCell<VecDeque<KeyEvent>>>,
+  log: Vec<(u64, Event)>,
+  recording: Option<File>,
+}
+
+impl EventQueue {
+  pub fn new() -> Self {
+    Self {
+      source: None,
+      scheduled: VecDeque::new(),
+      keys: Rc::new(RefCell::new(VecDeque::new())),
+      log: Vec::new(),
+      recording: None,
+    }
+  }
+
+  // The input provider to hand to the devices in place of the live one
+  pub fn input(&self) -> QueueInputProvider {
+    QueueInputProvider::new(Rc::clone(&self.keys))
+  }
+
+  // Read live key events from the given provider
+  pub fn listen(&mut self, source: Box<dyn InputProvider>) {
+    self.source = Some(source);
+  }
+
+  // Write each event to the file as it is delivered
+  pub fn record(&mut self, path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    self.recording = Some(file);
+    Ok(())
+  }
+
+  // Deliver the events from a recording at the same cycles they were logged
+  pub fn replay(&mut self, path: &str) -> Result<(), String> {
+    let text =
+      std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    for (number, line) in text.lines().enumerate() {
+      let (cycle, event) =
+        parse(line).ok_or_else(|| format!("Invalid event on line {}: {}", number + 1, line))?;
+      self.schedule(cycle, event);
+    }
+
+    Ok(())
+  }
+
+  pub fn schedule(&mut self, cycle: u64, event: Event) {
+    let index = self.scheduled.partition_point(|&(other, _)| other <= cycle);
+    self.scheduled.insert(index, (cycle, event));
+  }
+
+  // The cycle the next scheduled event is due on
+  pub fn next_scheduled(&self) -> Option<u64> {
+    self.scheduled.front().map(|&(cycle, _)| cycle)
+  }
+
+  // Everything delivered so far, in order
+  pub fn log(&self) -> &[(u64, Event)] {
+    &self.log
+  }
+
+  // Log the event, passing it on to the devices if it's a key press
+  pub fn push(&mut self, cycle: u64, event: Event) {
+    if let Event::Key(key) = event {
+      self.keys.borrow_mut().push_back(key);
+    }
+
+    if let Some(file) = &mut self.recording {
+      if writeln!(file, "{}", format(cycle, event)).is_err() {
+        println!("Failed to write to the event recording");
+        self.recording = None;
+      }
+    }
+
+    self.log.push((cycle, event));
+  }
+
+  // Collect the live key events and any scheduled events due by this cycle,
+  // along with the cycle each was due on, which are then delivered with push
+  pub fn poll(&mut self, cycle: u64) -> Vec<(u64, Event)> {
+    let mut due = Vec::new();
+
+    if let Some(source) = &mut self.source {
+      source.tick();
+      while let Some(key) = source.poll() {
+        due.push((cycle, Event::Key(key)));
+      }
+    }
+
+    while let Some(&(scheduled, event)) = self.scheduled.front() {
+      if scheduled > cycle {
+        break;
+      }
+      self.scheduled.pop_front();
+      due.push((scheduled, event));
+    }
+
+    due
+  }
+}
+
+fn format(cycle: u64, event: Event) -> String {
+  match event {
+    Event::Key(KeyEvent::Down(key)) => format!("{} down {:02X}", cycle, key),
+    Event::Key(KeyEvent::Up(key)) => format!("{} up {:02X}", cycle, key),
+    Event::Irq => format!("{} irq", cycle),
+    Event::Nmi => format!("{} nmi", cycle),
+  }
+}
+
+fn parse(line: &str) -> Option<(u64, Event)> {
+  let mut parts = line.split_whitespace();
+  let cycle = parts.next()?.parse().ok()?;
+
+  let event = match parts.next()? {
+    "down" => Event::Key(KeyEvent::Down(u8::from_str_radix(parts.next()?, 16).ok()?)),
+    "up" => Event::Key(KeyEvent::Up(u8::from_str_radix(parts.next()?, 16).ok()?)),
+    "irq" => Event::Irq,
+    "nmi" => Event::Nmi,
+    _ => return None,
+  };
+
+  match parts.next() {
+    Some(_) => None,
+    None => Some((cycle, event)),
+  }
+}
@@ -0,0 +1,66 @@
+use crate::system::{SaveState, System};
+use std::collections::VecDeque;
+
+// Save states taken every few frames, in a ring that drops the oldest once
+// it's full, for stepping back in time. Every snapshot holds a copy of the
+// writable memory, so the interval trades the memory and time spent saving
+// against how finely the run can be rewound: snapshotting every 10 frames
+// into 360 slots covers the last minute at 60 frames per second, for about
+// 12 MB with 32K of RAM, and at most 24 MB if all 64K is RAM. ROMs aren't
+// saved.
+pub struct RewindBuffer {
+  interval: u64,
+  capacity: usize,
+  frame: u64,
+  states: VecDeque<(u64, SaveState)>, // with the frame they were taken at
+}
+
+impl RewindBuffer {
+  pub fn new(interval: u64, capacity: usize) -> Self {
+    Self {
+      interval: interval.max(1),
+      capacity,
+      frame: 0,
+      states: VecDeque::new(),
+    }
+  }
+
+  // Call at the end of every frame
+  pub fn frame(&mut self, system: &System) {
+    self.frame += 1;
+
+    if self.frame.is_multiple_of(self.interval) && self.capacity > 0 {
+      if self.states.len() == self.capacity {
+        self.states.pop_front();
+      }
+      self.states.push_back((self.frame, system.save_state()));
+    }
+  }
+
+  // Go back to the latest snapshot from at least the given number of frames
+  // ago, forgetting the ones after it. Returns how many frames back that was,
+  // or None if there's no snapshot that old.
+  pub fn rewind(&mut self, system: &mut System, frames: u64) -> Option<u64> {
+    let target = self.frame.checked_sub(frames)?;
+    let index = self
+      .states
+      .iter()
+      .rposition(|&(taken, _)| taken <= target)?;
+    self.states.truncate(index + 1);
+
+    let (taken, state) = self.states.back().unwrap();
+    system.load_state(state);
+
+    let rewound = self.frame - taken;
+    self.frame = *taken;
+    Some(rewound)
+  }
+
+  // The number of frames that can be rewound
+  pub fn depth(&self) -> u64 {
+    self
+      .states
+      .front()
+      .map_or(0, |&(taken, _)| self.frame - taken)
+  }
+}
@@ -1,38 +1,271 @@
+use crate::audio::SampleClock;
+use crate::cycles::CYCLES;
+use crate::disassembler::{
+  disassemble_around, disassemble_labeled, disassemble_range, listing, Labels,
+};
 use crate::execute::Execute;
 use crate::fetch::Fetch;
+#[cfg(feature = "graphics")]
+use crate::graphics::{GraphicsProvider, Image};
 use crate::memory::Memory;
-use crate::registers::{flags, Registers};
+use crate::opcodes::{decode, jams, length, Mode};
+use crate::registers::{flags, RegisterSnapshot, Registers};
+use crate::replay::{Event, EventQueue};
+use crate::watchdog::Watchdog;
+use std::cell::Cell;
+#[cfg(feature = "graphics")]
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+// Cycles taken to push the return state and jump through the vector
+const INTERRUPT_CYCLES: u64 = 7;
+
+// A 1 MHz CPU running at 60 frames per second
+pub const CLOCK_RATE: u64 = 1_000_000;
+pub const FRAME_RATE: u64 = 60;
+pub const CYCLES_PER_FRAME: u64 = CLOCK_RATE / FRAME_RATE; // rounded down
+
+type OpcodeHandler = Box<dyn FnMut(&mut System) -> Result<(), CpuError>>;
 
 pub struct System {
   pub registers: Registers,
   memory: Box<dyn Memory>,
+  strict_memory: bool,
+  permissive: bool,
+  accurate_bus: bool,
+  cycles: u64,
+  wait_states: Cell<u64>, // from the current instruction's accesses
+  frame: Option<Frame>,
+  paced_from: u64, // cycle the frames from next_frame are counted from
+  paced_frames: u64,
+  executed: [bool; 256],
+  stack_base: u16,
+  watchdog: Option<Watchdog>,
+  irq_pending: bool,
+  nmi_pending: bool,
+  irq_line: bool, // held low by a level-triggered source
+  nmi_line: bool,
+  irq_requested: u64, // cycle each pending interrupt was requested on
+  nmi_requested: u64,
+  poll_cycle: u64, // interrupts requested after this wait another instruction
+  sample_clock: Option<SampleClock>,
+  last_pc: Option<u16>,
+  effective_address: Option<u16>, // of the last instruction's operand
+  transferred: Option<u8>,        // to or from the effective address
+  cycle_detail: CycleDetail,      // of the last instruction
+  breakpoints: HashSet<u16>,
+  frozen: HashMap<u16, u8>, // address -> value it's pinned to
+  events: EventQueue,
+  overrides: HashMap<u8, OpcodeHandler>,
+  hooks: HashMap<u16, OpcodeHandler>, // by the address of the routine
+  control_flow: Option<HashMap<(u16, u16), u64>>,
+  histogram: Option<Box<[u64; 256]>>, // times each opcode has executed
+  clock: Option<Rc<Cell<u64>>>,
+  variant: CpuVariant,
+  labels: Labels,
+  detect_stack_errors: bool,
+  stack_error: Option<StackError>, // from the current instruction
+  write_error: Option<CpuError>,   // likewise, in strict mode
+  on_stack_error: Option<Box<dyn FnMut(StackError)>>,
+  #[cfg(feature = "graphics")]
+  graphics: Option<Rc<RefCell<Box<dyn GraphicsProvider>>>>,
+  #[cfg(feature = "graphics")]
+  framebuffer: Image, // copied from the provider at the end of each run_frame
+}
+
+// How the operand of an instruction was resolved: the address it worked out
+// to and the value read from or written there (or the immediate value)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Access {
+  pub pc: u16,
+  pub mnemonic: &'static str,
+  pub mode: Mode,
+  pub operand: Vec<u8>,
+  pub address: Option<u16>,
+  pub value: Option<u8>,
+}
+
+// Why the last instruction took more than its base number of cycles
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CycleDetail {
+  pub page_crossed: bool, // by an indexed load
+  pub branch_taken: bool,
+  pub branch_crossed_page: bool,
+}
+
+// Everything needed to put a System back how it was: the CPU, and the
+// contents of the memory (see Memory::save_state)
+#[derive(Clone)]
+pub struct SaveState {
+  registers: Registers,
+  cycles: u64,
+  irq_pending: bool,
+  nmi_pending: bool,
+  memory: Vec<u8>,
+}
+
+impl SaveState {
+  // Roughly how many bytes the state takes up
+  pub fn size(&self) -> usize {
+    std::mem::size_of::<Self>() + self.memory.len()
+  }
+}
+
+// Where the CPU jumps to for each interrupt, from the top of memory
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vectors {
+  pub nmi: u16,
+  pub reset: u16,
+  pub irq: u16, // also used by BRK
+}
+
+// Which chip to behave like where the two differ, apart from the instruction
+// set, which is always the NMOS one
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuVariant {
+  Nmos,
+  // Decimal ADC and SBC take an extra cycle to set N and Z from the corrected
+  // result, and interrupts clear the decimal flag
+  Cmos,
+}
+
+// Why the CPU stopped
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuError {
+  Break, // BRK with no interrupt handler, which ROMs use to end the program
+  Jam { opcode: u8 },
+  UnimplementedOpcode { opcode: u8, pc: u16 },
+  BusError { address: u16 },              // only in strict mode
+  IllegalWrite { address: u16, pc: u16 }, // to ROM or nothing, only in strict mode
+  Stuck { start: u16, end: u16 },         // caught by the loop watchdog
+  Stack(StackError),
+}
+
+impl fmt::Display for CpuError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CpuError::Break => write!(f, "BRK with no interrupt handler"),
+      CpuError::Jam { opcode } => write!(f, "Jammed by opcode {:02X}", opcode),
+      CpuError::UnimplementedOpcode { opcode, pc } => {
+        write!(f, "Unimplemented opcode {:02X} at {:04X}", opcode, pc)
+      }
+      CpuError::BusError { address } => write!(f, "Bus error reading {:04X}", address),
+      CpuError::IllegalWrite { address, pc } => write!(
+        f,
+        "Illegal write to {:04X} by the instruction at {:04X}",
+        address, pc
+      ),
+      CpuError::Stuck { start, end } => {
+        write!(f, "Stuck in a loop between {:04X} and {:04X}", start, end)
+      }
+      CpuError::Stack(error) => write!(f, "{}", error),
+    }
+  }
+}
+
+// A push onto a full stack or a pop from an empty one, which would wrap the
+// stack pointer around the page. Each holds the PC of the last instruction
+// executed, which for an interrupt is the one before it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StackError {
+  Overflow(u16),
+  Underflow(u16),
+}
+
+impl fmt::Display for StackError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      StackError::Overflow(pc) => write!(f, "Stack overflow at {:04X}", pc),
+      StackError::Underflow(pc) => write!(f, "Stack underflow at {:04X}", pc),
+    }
+  }
+}
+
+struct Frame {
+  cycles_per_frame: u64,
+  next_frame: u64,
+  callback: Box<dyn FnMut(&mut System)>,
 }
 
 pub trait MemoryIO {
   fn read(&self, address: u16) -> u8;
   fn write(&mut self, address: u16, value: u8);
+
+  // Words are little-endian (low byte first), with the high byte of a word
+  // at 0xFFFF wrapping around to 0x0000
   fn read_word(&self, address: u16) -> u16;
   fn write_word(&mut self, address: u16, value: u16);
+
+  // Reads used by the CPU, which fail on bus errors in strict mode
+  fn try_read(&self, address: u16) -> Result<u8, CpuError>;
+  fn try_read_word(&self, address: u16) -> Result<u16, CpuError>;
 }
 
 impl MemoryIO for System {
   fn read(&self, address: u16) -> u8 {
-    self.memory.read(address)
+    self.wait(address);
+    match self.frozen.get(&address) {
+      Some(&value) => value,
+      None => self.memory.read(address),
+    }
+  }
+
+  fn try_read(&self, address: u16) -> Result<u8, CpuError> {
+    self.wait(address);
+
+    if let Some(&value) = self.frozen.get(&address) {
+      return Ok(value);
+    }
+
+    if !self.strict_memory {
+      return Ok(self.memory.read(address));
+    }
+
+    self
+      .memory
+      .try_read(address)
+      .map_err(|error| CpuError::BusError {
+        address: error.address,
+      })
+  }
+
+  fn try_read_word(&self, address: u16) -> Result<u16, CpuError> {
+    let lo = self.try_read(address)?;
+    let hi = self.try_read(address.wrapping_add(1))?;
+    Ok((hi as u16) << 8 | lo as u16)
   }
 
   fn read_word(&self, address: u16) -> u16 {
-    let lo = self.memory.read(address);
-    let hi = self.memory.read(address + 1);
+    let lo = self.read(address);
+    let hi = self.read(address.wrapping_add(1));
     (hi as u16) << 8 | lo as u16
   }
 
   fn write(&mut self, address: u16, value: u8) {
-    self.memory.write(address, value);
+    if let Some(watchdog) = &mut self.watchdog {
+      watchdog.write(address);
+    }
+
+    self.wait(address);
+    if self.frozen.contains_key(&address) {
+      return;
+    }
+
+    if !self.strict_memory {
+      self.memory.write(address, value);
+    } else if let Err(error) = self.memory.try_write(address, value) {
+      self.write_error.get_or_insert(CpuError::IllegalWrite {
+        address: error.address,
+        pc: self.last_pc.unwrap_or(0),
+      });
+    }
   }
 
   fn write_word(&mut self, address: u16, value: u16) {
-    self.memory.write(address, value as u8);
-    self.memory.write(address + 1, (value >> 8) as u8);
+    self.write(address, value as u8);
+    self.write(address.wrapping_add(1), (value >> 8) as u8);
   }
 }
 
@@ -45,38 +278,52 @@ pub trait Stack {
 }
 
 impl Stack for System {
+  // The stack pointer points at the next free byte
   fn push(&mut self, value: u8) {
+    if self.detect_stack_errors && self.registers.sp.get() == 0x00 {
+      self.stack_error = Some(StackError::Overflow(self.last_pc.unwrap_or(0)));
+    }
+
+    self.write(self.stack_address(), value);
     self.registers.sp.push();
-    self.write(self.registers.sp.address(), value);
   }
 
   fn pop(&mut self) -> u8 {
-    let value = self.read(self.registers.sp.address());
+    if self.detect_stack_errors && self.registers.sp.get() == 0xFF {
+      self.stack_error = Some(StackError::Underflow(self.last_pc.unwrap_or(0)));
+    }
+
     self.registers.sp.pop();
-    value
+    self.read(self.stack_address())
   }
 
+  // Words are pushed high byte first, leaving them little-endian in memory
   fn push_word(&mut self, value: u16) {
-    self.push((value & 0xFF) as u8);
     self.push((value >> 8) as u8);
+    self.push((value & 0xFF) as u8);
   }
 
   fn pop_word(&mut self) -> u16 {
-    let hi = self.pop();
     let lo = self.pop();
+    let hi = self.pop();
     (hi as u16) << 8 | lo as u16
   }
 }
 
 pub trait InterruptHandler {
-  fn interrupt(&mut self, maskable: bool);
+  // BRK goes through the IRQ vector, but pushes the status with B set
+  fn interrupt(&mut self, maskable: bool, brk: bool);
 }
 
 impl InterruptHandler for System {
-  fn interrupt(&mut self, maskable: bool) {
-    self.registers.sr.set(flags::INTERRUPT);
+  fn interrupt(&mut self, maskable: bool, brk: bool) {
     self.push_word(self.registers.pc.address());
-    self.push(self.registers.sr.get());
+    self.push(self.registers.sr.pushed_value(brk));
+    self.registers.sr.set(flags::INTERRUPT);
+
+    if self.variant == CpuVariant::Cmos {
+      self.registers.sr.clear(flags::DECIMAL);
+    }
 
     let dest = match maskable {
       false => self.read_word(0xFFFA),
@@ -89,22 +336,1662 @@ impl InterruptHandler for System {
 
 impl System {
   pub fn new(memory: Box<dyn Memory>) -> System {
+    #[cfg(feature = "validate")]
+    crate::opcodes::check_decode_table();
+
     System {
       registers: Registers::new(),
       memory,
+      strict_memory: false,
+      permissive: false,
+      accurate_bus: false,
+      cycles: 0,
+      wait_states: Cell::new(0),
+      frame: None,
+      paced_from: 0,
+      paced_frames: 0,
+      executed: [false; 256],
+      stack_base: 0x0100,
+      watchdog: None,
+      irq_pending: false,
+      nmi_pending: false,
+      irq_line: false,
+      nmi_line: false,
+      irq_requested: 0,
+      nmi_requested: 0,
+      poll_cycle: 0,
+      sample_clock: None,
+      last_pc: None,
+      effective_address: None,
+      transferred: None,
+      cycle_detail: CycleDetail::default(),
+      breakpoints: HashSet::new(),
+      frozen: HashMap::new(),
+      events: EventQueue::new(),
+      overrides: HashMap::new(),
+      hooks: HashMap::new(),
+      control_flow: None,
+      histogram: None,
+      clock: None,
+      variant: CpuVariant::Nmos,
+      labels: Labels::new(),
+      detect_stack_errors: false,
+      stack_error: None,
+      write_error: None,
+      on_stack_error: None,
+      #[cfg(feature = "graphics")]
+      graphics: None,
+      #[cfg(feature = "graphics")]
+      framebuffer: Image::new(0, 0),
+    }
+  }
+
+  // Start in the given state rather than from the reset vector, like a test
+  // ROM that has to be entered at a particular address
+  pub fn with_registers(memory: Box<dyn Memory>, registers: RegisterSnapshot) -> System {
+    let mut system = System::new(memory);
+    system.registers.restore(registers);
+    system
+  }
+
+  // Replace the built-in implementation of an opcode. The handler runs once
+  // the opcode has been fetched, with the PC pointing at its operand.
+  pub fn override_opcode(&mut self, opcode: u8, handler: OpcodeHandler) {
+    self.overrides.insert(opcode, handler);
+  }
+
+  // Returns whether the opcode had been overridden
+  pub fn remove_override(&mut self, opcode: u8) -> bool {
+    self.overrides.remove(&opcode).is_some()
+  }
+
+  // Handle the subroutine at the address on the host instead, like a KERNAL
+  // routine for character I/O. When the PC reaches it, the handler runs in
+  // place of the routine, which then returns as if by RTS.
+  pub fn hook(&mut self, address: u16, handler: OpcodeHandler) {
+    self.hooks.insert(address, handler);
+  }
+
+  // Returns whether the address had been hooked
+  pub fn remove_hook(&mut self, address: u16) -> bool {
+    self.hooks.remove(&address).is_some()
+  }
+
+  fn call_hook(&mut self, mut handler: OpcodeHandler) -> Result<(), CpuError> {
+    let pc = self.registers.pc.address();
+    self.last_pc = Some(pc);
+
+    let result = handler(self);
+    self.hooks.entry(pc).or_insert(handler);
+    result?;
+
+    let dest = self.pop_word().wrapping_add(1);
+    self.registers.pc.load(dest);
+    self.cycles += CYCLES[0x60] as u64;
+    Ok(())
+  }
+
+  pub fn add_breakpoint(&mut self, address: u16) {
+    self.breakpoints.insert(address);
+  }
+
+  // Returns whether there was a breakpoint at the address
+  pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+    self.breakpoints.remove(&address)
+  }
+
+  // Pin an address to a value, like a cheat: reads give the value without
+  // reaching the memory, and writes are dropped
+  pub fn freeze(&mut self, address: u16, value: u8) {
+    self.frozen.insert(address, value);
+  }
+
+  // Returns whether the address was frozen
+  pub fn unfreeze(&mut self, address: u16) -> bool {
+    self.frozen.remove(&address).is_some()
+  }
+
+  // Whether the next instruction to execute has a breakpoint on it
+  pub fn at_breakpoint(&self) -> bool {
+    self.breakpoints.contains(&self.registers.pc.address())
+  }
+
+  // Run until the PC reaches a breakpoint. At least one instruction is always
+  // executed, so this also resumes a program stopped at a breakpoint.
+  pub fn run_to_breakpoint(&mut self) -> Result<(), CpuError> {
+    loop {
+      self.try_tick()?;
+
+      if self.at_breakpoint() {
+        return Ok(());
+      }
+    }
+  }
+
+  // Run, ignoring breakpoints, until the subroutine currently executing
+  // returns to its caller. Nested calls and interrupts only push further
+  // below the stack pointer we start with, so returning from them never
+  // takes it back above.
+  pub fn run_to_return(&mut self) -> Result<(), CpuError> {
+    let watermark = self.registers.sp.get();
+
+    loop {
+      let pc = self.registers.pc.address();
+      let opcode = self.peek(pc);
+
+      self.try_tick()?;
+
+      // The interrupt might have been serviced instead of the instruction
+      let executed = self.last_pc == Some(pc);
+      if executed && matches!(opcode, 0x40 | 0x60) && self.registers.sp.get() > watermark {
+        return Ok(());
+      }
+    }
+  }
+
+  // A best guess at the chain of subroutine calls, innermost first, as the
+  // addresses of the JSRs. This is a heuristic: it walks up the stack from
+  // the stack pointer, taking any pair of bytes that would return to just
+  // after a JSR as a return address, so it skips over pushed registers but
+  // can be fooled by data that happens to look like one.
+  pub fn call_stack(&self) -> Vec<u16> {
+    let mut calls = Vec::new();
+    let mut offset = self.registers.sp.get() as u16 + 1;
+
+    while offset < 0xFF {
+      let lo = self.peek(self.stack_base.wrapping_add(offset));
+      let hi = self.peek(self.stack_base.wrapping_add(offset + 1));
+
+      // JSR pushes the address of its own last byte
+      let jsr = ((hi as u16) << 8 | lo as u16).wrapping_sub(2);
+
+      if self.peek(jsr) == 0x20 {
+        calls.push(jsr);
+        offset += 2;
+      } else {
+        offset += 1;
+      }
+    }
+
+    calls
+  }
+
+  // Run until the given address holds the value, as self-checking test
+  // programs do to report their status. Returns whether the value was seen
+  // within the instruction limit.
+  pub fn run_until_memory(
+    &mut self,
+    address: u16,
+    value: u8,
+    max_instructions: u64,
+  ) -> Result<bool, CpuError> {
+    for _ in 0..max_instructions {
+      if self.peek(address) == value {
+        return Ok(true);
+      }
+
+      self.try_tick()?;
+    }
+
+    Ok(self.peek(address) == value)
+  }
+
+  // Run until the stack pointer is back at the given value, like the one
+  // from before a chain of calls, once they've all returned. Returns whether
+  // it got there within the instruction limit.
+  pub fn run_until_sp(&mut self, target: u8, max_instructions: u64) -> Result<bool, CpuError> {
+    for _ in 0..max_instructions {
+      if self.registers.sp.get() == target {
+        return Ok(true);
+      }
+
+      self.try_tick()?;
+    }
+
+    Ok(self.registers.sp.get() == target)
+  }
+
+  // Only reads as many bytes as the instruction is long, to avoid touching
+  // any devices mapped just past it
+  fn instruction_bytes(&self, address: u16) -> [u8; 3] {
+    let opcode = self.peek(address);
+    let length = decode(opcode).map_or(1, |(_, mode)| mode.length());
+
+    let mut bytes = [opcode, 0, 0];
+    for offset in 1..length {
+      bytes[offset as usize] = self.peek(address.wrapping_add(offset));
+    }
+    bytes
+  }
+
+  // Read memory without triggering any device side effects
+  pub fn peek(&self, address: u16) -> u8 {
+    match self.frozen.get(&address) {
+      Some(&value) => value,
+      None => self.memory.peek(address),
+    }
+  }
+
+  fn peek_word(&self, address: u16) -> u16 {
+    let lo = self.peek(address);
+    let hi = self.peek(address.wrapping_add(1));
+    (hi as u16) << 8 | lo as u16
+  }
+
+  pub fn vectors(&self) -> Vectors {
+    Vectors {
+      nmi: self.peek_word(0xFFFA),
+      reset: self.peek_word(0xFFFC),
+      irq: self.peek_word(0xFFFE),
+    }
+  }
+
+  // Map a device in, or out again, after the system's been built. Anything
+  // already mapped over the range is hidden until it's unmapped.
+  pub fn map_device(
+    &mut self,
+    start: usize,
+    size: usize,
+    memory: Box<dyn Memory>,
+  ) -> Result<(), String> {
+    self.memory.map_device(start, size, memory)
+  }
+
+  pub fn unmap_device(&mut self, start: usize) -> Option<Box<dyn Memory>> {
+    self.memory.unmap_device(start)
+  }
+
+  // Load the ROMs again from their files and start over from a cold reset.
+  // If they can't be loaded, the memory is left as it was.
+  pub fn reload(&mut self) -> Result<(), String> {
+    self.memory.reload()?;
+    self.reset();
+    Ok(())
+  }
+
+  pub fn save_state(&self) -> SaveState {
+    SaveState {
+      registers: self.registers.clone(),
+      cycles: self.cycles,
+      irq_pending: self.irq_pending,
+      nmi_pending: self.nmi_pending,
+      memory: self.memory.save_state(),
+    }
+  }
+
+  // Restore a state saved from this System, or one with the same memory map.
+  // The next frame starts a whole frame after the restored cycle count, and
+  // the audio samples carry on from it.
+  pub fn load_state(&mut self, state: &SaveState) {
+    self.registers = state.registers.clone();
+    self.cycles = state.cycles;
+    self.paced_from = self.cycles;
+    self.paced_frames = 0;
+    self.irq_pending = state.irq_pending;
+    self.nmi_pending = state.nmi_pending;
+    self.irq_requested = self.cycles;
+    self.nmi_requested = self.cycles;
+    self.poll_cycle = self.cycles;
+    self.memory.load_state(&state.memory);
+
+    if let Some(frame) = &mut self.frame {
+      frame.next_frame = self.cycles + frame.cycles_per_frame;
+    }
+
+    if let Some(sample_clock) = &mut self.sample_clock {
+      sample_clock.restart(self.cycles);
+    }
+  }
+
+  // The whole address space as seen from the bus
+  pub fn memory_dump(&self) -> Vec<u8> {
+    (0..=0xFFFF).map(|address| self.peek(address)).collect()
+  }
+
+  // FNV-1a hash of the registers and memory, which is stable across runs and
+  // builds, for spotting where two emulators diverge
+  pub fn state_hash(&self) -> u64 {
+    let registers = [
+      (self.registers.pc.address() >> 8) as u8,
+      self.registers.pc.address() as u8,
+      self.registers.a,
+      self.registers.x,
+      self.registers.y,
+      self.registers.sp.get(),
+      self.registers.sr.get(),
+    ];
+
+    registers
+      .iter()
+      .chain(self.memory_dump().iter())
+      .fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+      })
+  }
+
+  // Names to show in place of addresses in disassembly, like ROM entry points
+  pub fn labels(&mut self, labels: Labels) {
+    self.labels = labels;
+  }
+
+  pub fn label_at(&self, address: u16) -> Option<&str> {
+    self.labels.get(&address).map(|label| label.as_str())
+  }
+
+  // Listing of the instructions between the two addresses (inclusive)
+  pub fn disassemble_range(&self, start: u16, end: u16) -> Vec<(u16, String)> {
+    disassemble_range(&*self.memory, start, end, &self.labels, false)
+  }
+
+  // The same, with each instruction's cycle count and penalties
+  pub fn disassemble_timed(&self, start: u16, end: u16) -> Vec<(u16, String)> {
+    disassemble_range(&*self.memory, start, end, &self.labels, true)
+  }
+
+  // Write the disassembly from start to end (inclusive) to a file. Data is
+  // disassembled as if it were code. With cycles, each instruction is
+  // annotated with its timing.
+  pub fn write_listing(
+    &self,
+    path: &str,
+    start: u16,
+    end: u16,
+    cycles: bool,
+  ) -> Result<(), String> {
+    let text = listing(&*self.memory, start, end, &self.labels, cycles);
+    std::fs::write(path, text).map_err(|error| format!("Couldn't write {}: {}", path, error))
+  }
+
+  // Listing of the instructions either side of the PC, with the ones before
+  // it found by a best guess (see disassembler::disassemble_around)
+  pub fn disassemble_around(&self, before: u16, after: u16) -> Vec<(u16, String)> {
+    let pc = self.registers.pc.address();
+    disassemble_around(&*self.memory, pc, before, after, &self.labels)
+  }
+
+  // Address of the most recently executed instruction
+  pub fn last_pc(&self) -> Option<u16> {
+    self.last_pc
+  }
+
+  // Disassembly of the most recently executed instruction
+  pub fn last_instruction(&self) -> String {
+    match self.last_pc {
+      Some(pc) => disassemble_labeled(pc, self.instruction_bytes(pc), &self.labels).0,
+      None => String::new(),
     }
   }
 
+  // The addressing of the most recently executed instruction. Branches give
+  // their target whether or not they were taken.
+  pub fn last_access(&self) -> Option<Access> {
+    let pc = self.last_pc?;
+    let bytes = self.instruction_bytes(pc);
+    let (mnemonic, mode) = decode(bytes[0])?;
+
+    let address = match mode {
+      Mode::Relative => Some(pc.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16)),
+      _ => self.effective_address,
+    };
+
+    Some(Access {
+      pc,
+      mnemonic,
+      mode,
+      operand: bytes[1..mode.length() as usize].to_vec(),
+      address,
+      value: self.transferred,
+    })
+  }
+
+  // Record where the current instruction's operand resolved to, for
+  // last_access
+  pub fn note_address(&mut self, address: u16) {
+    self.effective_address = Some(address);
+  }
+
+  pub fn note_transfer(&mut self, value: u8) {
+    self.transferred = Some(value);
+  }
+
+  // Request a maskable interrupt, which is serviced once the current
+  // instruction finishes and the interrupt disable flag is clear
+  pub fn irq(&mut self) {
+    self.request_irq(self.cycles);
+  }
+
+  // Request a non-maskable interrupt, serviced once the current instruction
+  // finishes
+  pub fn nmi(&mut self) {
+    self.request_nmi(self.cycles);
+  }
+
+  fn request_irq(&mut self, cycle: u64) {
+    if !self.irq_pending() {
+      self.irq_requested = cycle;
+    }
+    self.irq_pending = true;
+    self.events.push(cycle, Event::Irq);
+  }
+
+  fn request_nmi(&mut self, cycle: u64) {
+    if !self.nmi_pending {
+      self.nmi_requested = cycle;
+    }
+    self.nmi_pending = true;
+    self.events.push(cycle, Event::Nmi);
+  }
+
+  // Whether an IRQ will be serviced once interrupts are enabled, either from
+  // irq or from the line being held
+  pub fn irq_pending(&self) -> bool {
+    self.irq_pending || self.irq_line
+  }
+
+  pub fn nmi_pending(&self) -> bool {
+    self.nmi_pending
+  }
+
+  // Hold the IRQ line, like a device that keeps requesting an interrupt until
+  // it's been serviced, so the handler runs again each time interrupts are
+  // enabled until the line is released. Unlike irq, changes to the lines
+  // aren't logged to the event queue.
+  pub fn set_irq_line(&mut self, asserted: bool) {
+    if asserted && !self.irq_pending() {
+      self.irq_requested = self.cycles;
+    }
+
+    self.irq_line = asserted;
+  }
+
+  // The NMI line is edge-triggered: asserting it requests one interrupt,
+  // and it has to be released before it can request another
+  pub fn set_nmi_line(&mut self, asserted: bool) {
+    if asserted && !self.nmi_line {
+      if !self.nmi_pending {
+        self.nmi_requested = self.cycles;
+      }
+      self.nmi_pending = true;
+    }
+
+    self.nmi_line = asserted;
+  }
+
+  // Cancel a requested interrupt that hasn't been serviced yet. A held IRQ
+  // line stays asserted.
+  pub fn clear_irq(&mut self) {
+    self.irq_pending = false;
+  }
+
+  pub fn clear_nmi(&mut self) {
+    self.nmi_pending = false;
+  }
+
+  // Route external events through the given queue, so they can be recorded
+  // or replayed
+  pub fn event_queue(&mut self, events: EventQueue) {
+    self.events = events;
+  }
+
+  pub fn events(&self) -> &EventQueue {
+    &self.events
+  }
+
+  // Record every taken branch, jump, call and return as an edge between the
+  // address of the instruction and where it went
+  pub fn log_control_flow(&mut self, enabled: bool) {
+    self.control_flow = match enabled {
+      true => Some(HashMap::new()),
+      false => None,
+    };
+  }
+
+  // The edges recorded so far, with how many times each was taken, in order
+  // of the source address
+  pub fn control_flow(&self) -> Vec<((u16, u16), u64)> {
+    let mut edges: Vec<_> = self
+      .control_flow
+      .iter()
+      .flatten()
+      .map(|(&edge, &count)| (edge, count))
+      .collect();
+    edges.sort();
+    edges
+  }
+
+  // Count how many times each opcode executes, from now until the next reset
+  pub fn count_opcodes(&mut self, enabled: bool) {
+    self.histogram = match enabled {
+      true => Some(Box::new([0; 256])),
+      false => None,
+    };
+  }
+
+  // All zero unless count_opcodes is enabled
+  pub fn opcode_histogram(&self) -> [u64; 256] {
+    self.histogram.as_deref().copied().unwrap_or([0; 256])
+  }
+
+  // Halt when the PC stays within a small loop for more than `limit`
+  // instructions without writing to memory outside of it
+  pub fn loop_watchdog(&mut self, limit: Option<u64>) {
+    self.watchdog = limit.map(Watchdog::new);
+  }
+
+  // The stack pointer is an offset into the page starting at this address
+  pub fn set_stack_base(&mut self, base: u16) {
+    self.stack_base = base;
+  }
+
+  fn wait(&self, address: u16) {
+    let cycles = self.memory.wait_states(address) as u64;
+    self.wait_states.set(self.wait_states.get() + cycles);
+  }
+
+  fn stack_address(&self) -> u16 {
+    self.stack_base.wrapping_add(self.registers.sp.get() as u16)
+  }
+
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  // Keep the cycle count in a cell shared with devices that timestamp what
+  // the CPU does to them, like SpeakerMemory, or that run on their own
+  // clock, like ClockedMemory. It's updated before the devices tick and at
+  // the start of each instruction.
+  pub fn clock(&mut self, clock: Rc<Cell<u64>>) {
+    clock.set(self.cycles);
+    self.clock = Some(clock);
+  }
+
+  // The earliest cycle at which a device, a scheduled event or the end of
+  // the frame needs attention, so an idle CPU can be skipped ahead to it
+  pub fn next_event_cycle(&self) -> Option<u64> {
+    let device = self.memory.next_event().map(|delay| self.cycles + delay);
+    let scheduled = self.events.next_scheduled();
+    let frame = self.frame.as_ref().map(|frame| frame.next_frame);
+
+    [device, scheduled, frame].into_iter().flatten().min()
+  }
+
+  // Call the given callback once for each audio sample, given the CPU's clock
+  // rate and the sample rate in Hz
+  pub fn on_sample(&mut self, clock_rate: u64, sample_rate: u64, callback: Box<dyn FnMut(u64)>) {
+    self.sample_clock = Some(SampleClock::new(
+      clock_rate,
+      sample_rate,
+      self.cycles,
+      callback,
+    ));
+  }
+
+  // Implemented opcodes that haven't been executed yet, for finding gaps in
+  // test programs
+  pub fn uncovered_opcodes(&self) -> Vec<u8> {
+    (0..=0xFF)
+      .filter(|&opcode| CYCLES[opcode as usize] != 0 && !self.executed[opcode as usize])
+      .collect()
+  }
+
+  pub fn print_coverage(&self) {
+    let uncovered = self.uncovered_opcodes();
+    let implemented = CYCLES.iter().filter(|&&cycles| cycles != 0).count();
+
+    println!(
+      "Executed {} of {} implemented opcodes",
+      implemented - uncovered.len(),
+      implemented
+    );
+
+    if !uncovered.is_empty() {
+      let listing: Vec<String> = uncovered.iter().map(|op| format!("{:02X}", op)).collect();
+      println!("Never executed: {}", listing.join(" "));
+    }
+  }
+
+  // Call the given callback every time another frame's worth of cycles elapses
+  pub fn on_frame(&mut self, cycles_per_frame: u64, callback: Box<dyn FnMut(&mut System)>) {
+    self.frame = Some(Frame {
+      cycles_per_frame,
+      next_frame: self.cycles + cycles_per_frame,
+      callback,
+    });
+  }
+
+  // Start another frame, returning the cycle to run up to. Each frame ends
+  // where it's due to rather than a frame after the last instruction of the
+  // one before, so the overrun carries over and frames average out to
+  // exactly CLOCK_RATE / FRAME_RATE cycles, fractions included. If something
+  // else has run the CPU past the end, pacing starts again from here.
+  pub fn next_frame(&mut self) -> u64 {
+    self.paced_frames += 1;
+    let end = self.paced_from + self.paced_frames * CLOCK_RATE / FRAME_RATE;
+
+    if self.cycles < end {
+      return end;
+    }
+
+    self.paced_from = self.cycles;
+    self.paced_frames = 1;
+    self.cycles + CLOCK_RATE / FRAME_RATE
+  }
+
+  // Halt on reads from devices that aren't there, and on writes to ROM or to
+  // nothing, which are almost always a bug in the program or the memory map
+  pub fn strict_memory(&mut self, strict: bool) {
+    self.strict_memory = strict;
+  }
+
+  // Make the spurious bus accesses the NMOS 6502 does along the way, for
+  // devices that react to every access: read-modify-write instructions write
+  // the original value back before the modified one, and indexed loads that
+  // cross a page read from the address before its high byte is fixed up
+  pub fn accurate_bus(&mut self, enabled: bool) {
+    self.accurate_bus = enabled;
+  }
+
+  // The write at the end of a read-modify-write instruction. The write of
+  // the original value stalls the CPU for its wait states either way, since
+  // the hardware always makes it.
+  pub fn modify(&mut self, address: u16, original: u8, result: u8) {
+    if self.accurate_bus {
+      self.write(address, original);
+    } else {
+      self.wait(address);
+    }
+
+    self.write(address, result);
+    self.note_transfer(result);
+  }
+
+  // The extra cycle a load takes when its indexed address crosses a page,
+  // reading from the address before the high byte is fixed up
+  pub fn cross_page(&mut self, unfixed: u16) {
+    self.dummy_read(unfixed);
+    self.cycle_detail.page_crossed = true;
+    self.cycles += 1;
+  }
+
+  // Branching takes an extra cycle, and another if the target is on a
+  // different page to the next instruction
+  pub fn take_branch(&mut self, offset: i8) {
+    let next = self.registers.pc.address();
+    self.registers.pc.offset(offset);
+    self.cycle_detail.branch_taken = true;
+    self.cycles += 1;
+
+    if self.registers.pc.address() & 0xFF00 != next & 0xFF00 {
+      self.cycle_detail.branch_crossed_page = true;
+      self.cycles += 1;
+    }
+  }
+
+  // What added to the base cycle count of the most recently executed
+  // instruction
+  pub fn last_cycle_detail(&self) -> CycleDetail {
+    self.cycle_detail
+  }
+
+  // A read whose value is thrown away, which only reaches the device on an
+  // accurate bus, but costs its wait states either way
+  pub fn dummy_read(&self, address: u16) {
+    if self.accurate_bus {
+      self.read(address);
+    } else {
+      self.wait(address);
+    }
+  }
+
+  pub fn cpu_variant(&mut self, variant: CpuVariant) {
+    self.variant = variant;
+  }
+
+  // Called after ADC and SBC, which set N and Z from the binary result in
+  // decimal mode unless the CPU is a 65C02
+  pub fn decimal_flags(&mut self) {
+    if self.variant == CpuVariant::Cmos && self.registers.sr.read(flags::DECIMAL) {
+      self.registers.sr.set_nz(self.registers.a);
+      self.cycles += 1;
+    }
+  }
+
+  // Halt when the stack pointer wraps around, which is almost always down to
+  // mismatched pushes and pops, rather than carrying on with a corrupt stack.
+  // The error is also passed to the on_stack_error callback, if there is one.
+  pub fn detect_stack_errors(&mut self, enabled: bool) {
+    self.detect_stack_errors = enabled;
+  }
+
+  pub fn on_stack_error(&mut self, callback: Box<dyn FnMut(StackError)>) {
+    self.on_stack_error = Some(callback);
+  }
+
+  // Treat unimplemented opcodes as NOPs that skip over their operand bytes,
+  // rather than halting
+  pub fn permissive_mode(&mut self, permissive: bool) {
+    self.permissive = permissive;
+  }
+
   pub fn reset(&mut self) {
+    self.irq_pending = false;
+    self.nmi_pending = false;
+    self.irq_line = false;
+    self.nmi_line = false;
+    self.poll_cycle = self.cycles;
+    if let Some(histogram) = &mut self.histogram {
+      histogram.fill(0);
+    }
     self.memory.reset();
     self.registers.reset();
     self.registers.pc.load(self.read_word(0xFFFC));
+    self.wait_states.set(0);
+    self.stack_error = None;
   }
 
   pub fn tick(&mut self) {
+    self.try_tick().expect("Failed to execute instruction");
+  }
+
+  pub fn try_tick(&mut self) -> Result<(), CpuError> {
+    for (cycle, event) in self.events.poll(self.cycles) {
+      match event {
+        Event::Irq => self.request_irq(cycle),
+        Event::Nmi => self.request_nmi(cycle),
+        Event::Key(_) => self.events.push(self.cycles, event),
+      }
+    }
+
+    // Devices on their own clocks catch up to here
+    if let Some(clock) = &self.clock {
+      clock.set(self.cycles);
+    }
+
     self.memory.tick();
+    self.step()
+  }
+
+  // Execute a single instruction
+  pub fn step(&mut self) -> Result<(), CpuError> {
+    if let Some(clock) = &self.clock {
+      clock.set(self.cycles);
+    }
+
+    if let Some(watchdog) = &mut self.watchdog {
+      if watchdog.visit(self.registers.pc.address()) {
+        let (start, end) = watchdog.range();
+        return Err(CpuError::Stuck { start, end });
+      }
+    }
+
+    // Interrupts are only recognized between instructions, and only if they
+    // were requested before the last one polled for them
+    let mut late_poll = false;
+    if self.nmi_pending && self.nmi_requested <= self.poll_cycle {
+      self.nmi_pending = false;
+      self.interrupt(false, false);
+      self.cycles += INTERRUPT_CYCLES;
+    } else if self.irq_pending()
+      && self.irq_requested <= self.poll_cycle
+      && !self.registers.sr.read(flags::INTERRUPT)
+    {
+      self.irq_pending = false;
+      self.interrupt(true, false);
+      self.cycles += INTERRUPT_CYCLES;
+    } else if let Some(handler) = self.hooks.remove(&self.registers.pc.address()) {
+      self.call_hook(handler)?;
+    } else {
+      let pc = self.registers.pc.address();
+      self.last_pc = Some(pc);
+      self.effective_address = None;
+      self.transferred = None;
+      self.cycle_detail = CycleDetail::default();
+      self.write_error = None;
+      let opcode = self.fetch()?;
+
+      if let Some(mut handler) = self.overrides.remove(&opcode) {
+        let result = handler(self);
+
+        // The handler may have replaced itself
+        self.overrides.entry(opcode).or_insert(handler);
+        result?;
+
+        self.cycles += CYCLES[opcode as usize] as u64;
+        self.executed[opcode as usize] = true;
+      } else if self.permissive && decode(opcode).is_none() && !jams(opcode) {
+        for _ in 1..length(opcode) {
+          self.registers.pc.increment();
+        }
+        self.cycles += CYCLES[0xEA] as u64;
+      } else {
+        self.execute(opcode)?;
+        self.cycles += CYCLES[opcode as usize] as u64;
+        self.executed[opcode as usize] = true;
+      }
+
+      if let Some(edges) = &mut self.control_flow {
+        let to = self.registers.pc.address();
+        if to != pc.wrapping_add(length(opcode)) {
+          *edges.entry((pc, to)).or_insert(0) += 1;
+        }
+      }
+
+      if let Some(histogram) = &mut self.histogram {
+        histogram[opcode as usize] += 1;
+      }
+
+      // Instructions poll for interrupts before their last cycle, except a
+      // taken branch that stays on its page, which doesn't poll in its extra
+      // cycle. An interrupt requested during that cycle waits until after
+      // the next instruction.
+      late_poll = self.cycle_detail.branch_taken && !self.cycle_detail.branch_crossed_page;
+    }
+
+    self.cycles += self.wait_states.take();
+    self.poll_cycle = self.cycles - late_poll as u64;
+
+    if let Some(error) = self.stack_error.take() {
+      if let Some(callback) = &mut self.on_stack_error {
+        callback(error);
+      }
+      return Err(CpuError::Stack(error));
+    }
+
+    if let Some(error) = self.write_error.take() {
+      return Err(error);
+    }
+
+    if let Some(sample_clock) = &mut self.sample_clock {
+      sample_clock.advance(self.cycles);
+    }
+
+    if let Some(frame) = &self.frame {
+      if self.cycles >= frame.next_frame {
+        self.end_frame();
+      }
+    }
+
+    #[cfg(feature = "validate")]
+    self.validate();
+
+    Ok(())
+  }
+
+  // Check the invariants the CPU should always keep, blaming the instruction
+  // that broke one
+  #[cfg(feature = "validate")]
+  fn validate(&self) {
+    let pc = self.registers.pc.address();
+    let culprit = || match self.last_pc {
+      Some(last) => format!("opcode {:02X} at {:04X}", self.peek(last), last),
+      None => "an interrupt".to_string(),
+    };
+
+    assert!(
+      self.registers.sr.read(flags::UNUSED),
+      "Status bit 5 was cleared by {}",
+      culprit()
+    );
+    assert!(
+      self.memory.mapped(pc),
+      "PC left mapped memory for {:04X} after {}",
+      pc,
+      culprit()
+    );
+    assert!(
+      self.memory.mapped(self.stack_address()),
+      "SP left mapped memory for {:04X} after {}",
+      self.stack_address(),
+      culprit()
+    );
+  }
+
+  // The provider the devices draw to, shared with them, for run_frame to
+  // render and read back
+  #[cfg(feature = "graphics")]
+  pub fn graphics(&mut self, graphics: Rc<RefCell<Box<dyn GraphicsProvider>>>) {
+    self.graphics = Some(graphics);
+  }
+
+  #[cfg(feature = "graphics")]
+  pub fn run_frame(&mut self) -> &Image {
+    self.try_run_frame().expect("Failed to execute instruction")
+  }
+
+  // Execute up to the next frame boundary (see on_frame, or next_frame
+  // without one), delivering events and interrupts along the way, then render
+  // and return the framebuffer. It's empty without a graphics provider.
+  #[cfg(feature = "graphics")]
+  pub fn try_run_frame(&mut self) -> Result<&Image, CpuError> {
+    let end = match &self.frame {
+      Some(frame) => frame.next_frame,
+      None => self.next_frame(),
+    };
+
+    while self.cycles < end {
+      self.try_tick()?;
+    }
+
+    if let Some(graphics) = &self.graphics {
+      let mut graphics = graphics.borrow_mut();
+      graphics.tick();
+      self.framebuffer.clone_from(graphics.image());
+    }
+
+    Ok(&self.framebuffer)
+  }
+
+  fn end_frame(&mut self) {
+    let mut frame = self.frame.take().unwrap();
+    frame.next_frame += frame.cycles_per_frame;
+    (frame.callback)(self);
+
+    // The callback may have replaced itself
+    if self.frame.is_none() {
+      self.frame = Some(frame);
+    }
+  }
+
+  // Write the bytes through the memory bus, so read-only regions are left
+  // untouched
+  pub fn patch(&mut self, address: u16, data: &[u8]) {
+    for (offset, &value) in data.iter().enumerate() {
+      self.write(address.wrapping_add(offset as u16), value);
+    }
+  }
+
+  pub fn nop_fill(&mut self, address: u16, len: u16) {
+    self.patch(address, &vec![0xEA; len as usize]);
+  }
+
+  // Write the bytes to the start of the zero page, for setting up pointers
+  pub fn set_zero_page(&mut self, data: &[u8]) {
+    for (address, &value) in data.iter().enumerate().take(0x100) {
+      self.write(address as u16, value);
+    }
+  }
+
+  // Push each byte onto the stack in order, so the last one ends up on top
+  pub fn push_bytes(&mut self, data: &[u8]) {
+    for &value in data {
+      self.push(value);
+    }
+  }
+
+  // Run the subroutine at the given address as if it were called with JSR,
+  // until it executes the RTS matching that call. Returns whether it did
+  // within the instruction limit.
+  pub fn call_subroutine(&mut self, address: u16, max_instructions: u64) -> Result<bool, CpuError> {
+    let return_address = self.registers.pc.address();
+    let sp = self.registers.sp.get();
+
+    self.push_word(return_address.wrapping_sub(1));
+    self.registers.pc.load(address);
+
+    let returned = |system: &System| {
+      system.registers.pc.address() == return_address && system.registers.sp.get() == sp
+    };
+
+    for _ in 0..max_instructions {
+      if returned(self) {
+        return Ok(true);
+      }
+
+      self.try_tick()?;
+    }
+
+    Ok(returned(self))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::memory::{BlockMemory, ScriptedMemory};
+  use std::cell::RefCell;
+
+  const PROGRAM: u16 = 0x0200;
+  const IRQ_HANDLER: u16 = 0x0300;
+  const NMI_HANDLER: u16 = 0x0400;
+
+  // RAM with the program at $0200, a NOP at each handler and interrupts
+  // enabled
+  fn system(program: &[u8]) -> System {
+    with_memory(Box::new(BlockMemory::ram(0x10000)), program)
+  }
+
+  fn with_memory(memory: Box<dyn Memory>, program: &[u8]) -> System {
+    let mut system = System::new(memory);
+    system.reset();
+    system.patch(0xFFFA, &[0x00, 0x04]);
+    system.patch(0xFFFE, &[0x00, 0x03]);
+    system.patch(IRQ_HANDLER, &[0xEA]);
+    system.patch(NMI_HANDLER, &[0xEA]);
+    system.patch(PROGRAM, program);
+    system.registers.pc.load(PROGRAM);
+    system.registers.sr.clear(flags::INTERRUPT);
+    system
+  }
+
+  // The CPU's accesses to the device at $1000-$1FFF, and the wait states it
+  // stalls the CPU for on each
+  #[derive(Default)]
+  struct Bus {
+    reads: Cell<u32>,
+    writes: Cell<u32>,
+    written: RefCell<Vec<u8>>, // the values, in order
+    wait_states: Cell<u8>,
+  }
+
+  struct CountingMemory {
+    ram: BlockMemory,
+    bus: Rc<Bus>,
+  }
+
+  fn on_device(address: u16) -> bool {
+    (0x1000..0x2000).contains(&address)
+  }
+
+  impl Memory for CountingMemory {
+    fn read(&self, address: u16) -> u8 {
+      if on_device(address) {
+        self.bus.reads.set(self.bus.reads.get() + 1);
+      }
+      self.ram.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+      if on_device(address) {
+        self.bus.writes.set(self.bus.writes.get() + 1);
+        self.bus.written.borrow_mut().push(value);
+      }
+      self.ram.write(address, value);
+    }
+
+    fn tick(&mut self) {}
+
+    fn reset(&mut self) {
+      self.ram.reset();
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+      self.ram.peek(address)
+    }
+
+    fn wait_states(&self, address: u16) -> u8 {
+      match on_device(address) {
+        true => self.bus.wait_states.get(),
+        false => 0,
+      }
+    }
+  }
 
-    let opcode = self.fetch();
-    self.execute(opcode).expect("Failed to execute instruction");
+  fn counting_system(program: &[u8]) -> (System, Rc<Bus>) {
+    let bus = Rc::new(Bus::default());
+    let memory = CountingMemory {
+      ram: BlockMemory::ram(0x10000),
+      bus: Rc::clone(&bus),
+    };
+    (with_memory(Box::new(memory), program), bus)
+  }
+
+  #[test]
+  fn irq_is_taken_at_the_next_instruction_boundary() {
+    let mut system = system(&[0xEA, 0xEA, 0xEA]);
+    system.step().unwrap();
+    system.irq();
+    let cycles = system.cycles();
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), IRQ_HANDLER);
+    assert_eq!(system.cycles() - cycles, INTERRUPT_CYCLES);
+    assert!(system.registers.sr.read(flags::INTERRUPT));
+    assert!(!system.irq_pending());
+
+    // The return address, then the status with B clear
+    let sp = system.registers.sp.get() as u16;
+    assert_eq!(system.peek(0x0103 + sp), 0x02);
+    assert_eq!(system.peek(0x0102 + sp), 0x01);
+    assert_eq!(system.peek(0x0101 + sp) & flags::BREAK, 0);
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), IRQ_HANDLER + 1);
+  }
+
+  #[test]
+  fn nmi_takes_priority_over_irq() {
+    let mut system = system(&[0xEA]);
+    system.irq();
+    system.nmi();
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), NMI_HANDLER);
+    assert!(!system.nmi_pending());
+    assert!(system.irq_pending());
+
+    // The NMI set the interrupt disable flag, so the IRQ waits
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), NMI_HANDLER + 1);
+    assert!(system.irq_pending());
+  }
+
+  #[test]
+  fn irq_is_masked_by_the_interrupt_disable_flag() {
+    // SEI, NOP, NOP
+    let mut system = system(&[0x78, 0xEA, 0xEA]);
+    system.step().unwrap();
+    system.irq();
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), PROGRAM + 2);
+    assert!(system.irq_pending());
+
+    system.registers.sr.clear(flags::INTERRUPT);
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), IRQ_HANDLER);
+  }
+
+  #[test]
+  fn irq_after_a_branch_on_the_same_page_waits_an_instruction() {
+    // BNE to the next instruction, NOP, NOP
+    let mut system = system(&[0xD0, 0x00, 0xEA, 0xEA]);
+    system.registers.sr.clear(flags::ZERO);
+    system.step().unwrap();
+    system.irq();
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), PROGRAM + 3);
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), IRQ_HANDLER);
+  }
+
+  #[test]
+  fn irq_after_a_branch_across_a_page_is_on_time() {
+    let mut system = system(&[]);
+    // BNE from $05FC over to $0600, NOP
+    system.patch(0x05FC, &[0xD0, 0x02]);
+    system.patch(0x0600, &[0xEA]);
+    system.registers.pc.load(0x05FC);
+    system.registers.sr.clear(flags::ZERO);
+    system.step().unwrap();
+    assert!(system.last_cycle_detail().branch_crossed_page);
+    system.irq();
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), IRQ_HANDLER);
+  }
+
+  // SED, CLC, LDA #$99, ADC #$01, returning the cycles the ADC took
+  fn decimal_add(variant: CpuVariant) -> (System, u64) {
+    let mut system = system(&[0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01]);
+    system.cpu_variant(variant);
+    for _ in 0..3 {
+      system.step().unwrap();
+    }
+
+    let cycles = system.cycles();
+    system.step().unwrap();
+    assert_eq!(system.registers.a, 0x00);
+    let cycles = system.cycles() - cycles;
+    (system, cycles)
+  }
+
+  #[test]
+  fn nmos_decimal_flags_come_from_the_binary_result() {
+    let (system, cycles) = decimal_add(CpuVariant::Nmos);
+    assert!(!system.registers.sr.read(flags::ZERO));
+    assert!(system.registers.sr.read(flags::NEGATIVE));
+    assert_eq!(cycles, 2);
+  }
+
+  #[test]
+  fn cmos_decimal_flags_come_from_the_corrected_result() {
+    let (system, cycles) = decimal_add(CpuVariant::Cmos);
+    assert!(system.registers.sr.read(flags::ZERO));
+    assert!(!system.registers.sr.read(flags::NEGATIVE));
+    assert_eq!(cycles, 3);
+  }
+
+  #[test]
+  fn only_cmos_interrupts_clear_the_decimal_flag() {
+    for (variant, decimal) in [(CpuVariant::Nmos, true), (CpuVariant::Cmos, false)] {
+      let mut system = system(&[0xF8, 0xEA]);
+      system.cpu_variant(variant);
+      system.step().unwrap();
+      system.irq();
+
+      system.step().unwrap();
+      assert_eq!(system.registers.pc.address(), IRQ_HANDLER);
+      assert_eq!(system.registers.sr.read(flags::DECIMAL), decimal);
+    }
+  }
+
+  #[test]
+  fn adc_and_sbc_read_the_carry_flag() {
+    // CLC or SEC, LDA #$10, then ADC #$01 or SBC #$01
+    let cases = [
+      (0x18, 0x69, 0x11),
+      (0x38, 0x69, 0x12),
+      (0x18, 0xE9, 0x0E),
+      (0x38, 0xE9, 0x0F),
+    ];
+
+    for (carry, opcode, result) in cases {
+      let mut system = system(&[carry, 0xA9, 0x10, opcode, 0x01]);
+      for _ in 0..3 {
+        system.step().unwrap();
+      }
+      assert_eq!(
+        system.registers.a, result,
+        "{:02X} then {:02X}",
+        carry, opcode
+      );
+    }
+  }
+
+  #[test]
+  fn txs_leaves_the_flags_alone() {
+    for (x, preset) in [(0x00, flags::ZERO), (0x00, 0), (0x80, flags::ZERO)] {
+      let mut system = system(&[0x9A]);
+      system.registers.x = x;
+      system.registers.sr.clear(flags::ZERO | flags::NEGATIVE);
+      system.registers.sr.set(preset);
+      let status = system.registers.sr.get();
+
+      system.step().unwrap();
+      assert_eq!(system.registers.sp.get(), x);
+      assert_eq!(system.registers.sr.get(), status, "TXS with X={:02X}", x);
+    }
+  }
+
+  #[test]
+  fn tsx_sets_n_and_z() {
+    for (sp, n, z) in [
+      (0x00, false, true),
+      (0x80, true, false),
+      (0x7F, false, false),
+    ] {
+      let mut system = system(&[0xBA]);
+      system.registers.sp.set(sp);
+      system.registers.sr.write(flags::ZERO, !z);
+      system.registers.sr.write(flags::NEGATIVE, !n);
+
+      system.step().unwrap();
+      assert_eq!(system.registers.x, sp);
+      assert_eq!(
+        system.registers.sr.read(flags::NEGATIVE),
+        n,
+        "TSX with SP={:02X}",
+        sp
+      );
+      assert_eq!(
+        system.registers.sr.read(flags::ZERO),
+        z,
+        "TSX with SP={:02X}",
+        sp
+      );
+    }
+  }
+
+  #[test]
+  fn status_pushes_set_b_only_for_php_and_brk() {
+    // The interrupts are taken in place of the NOP
+    let cases = [
+      ("PHP", 0x08, 0xB1),
+      ("BRK", 0x00, 0xB1),
+      ("IRQ", 0xEA, 0xA1),
+      ("NMI", 0xEA, 0xA1),
+    ];
+
+    for (name, opcode, expected) in cases {
+      let mut system = system(&[opcode]);
+      // N, C and the unused bit, with B clear
+      system.registers.sr.load(0xA1);
+      match name {
+        "IRQ" => system.irq(),
+        "NMI" => system.nmi(),
+        _ => {}
+      }
+
+      system.step().unwrap();
+      let pushed = system.peek(0x0101 + system.registers.sp.get() as u16);
+      assert_eq!(pushed, expected, "{}", name);
+    }
+  }
+
+  #[test]
+  fn status_pulls_ignore_b_and_the_unused_bit() {
+    for (name, opcode) in [("PLP", 0x28), ("RTI", 0x40)] {
+      for (pulled, status) in [(0x00, 0x30), (0xCF, 0xFF), (0x81, 0xB1), (0xB1, 0xB1)] {
+        let mut system = system(&[opcode]);
+        // Below the status, the return address for RTI
+        system.push_bytes(&[0x02, 0x00, pulled]);
+
+        system.step().unwrap();
+        assert_eq!(
+          system.registers.sr.get(),
+          status,
+          "{} of {:02X}",
+          name,
+          pulled
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn read_modify_write_reads_its_operand_once() {
+    // INC, DEC, ASL, LSR, ROL and ROR of $1000
+    for opcode in [0xEE, 0xCE, 0x0E, 0x4E, 0x2E, 0x6E] {
+      for accurate in [false, true] {
+        let (mut system, bus) = counting_system(&[opcode, 0x00, 0x10]);
+        system.accurate_bus(accurate);
+
+        system.step().unwrap();
+        assert_eq!(bus.reads.get(), 1, "{:02X}", opcode);
+      }
+    }
+  }
+
+  #[test]
+  fn read_modify_write_writes_back_the_original_on_an_accurate_bus() {
+    for (accurate, writes) in [(false, 1), (true, 2)] {
+      // INC $1000
+      let (mut system, bus) = counting_system(&[0xEE, 0x00, 0x10]);
+      system.patch(0x1000, &[0x41]);
+      bus.writes.set(0);
+      system.accurate_bus(accurate);
+
+      system.step().unwrap();
+      assert_eq!(bus.writes.get(), writes);
+      assert_eq!(system.peek(0x1000), 0x42);
+    }
+  }
+
+  #[test]
+  fn indexed_loads_make_a_dummy_read_only_across_a_page() {
+    // LDA $10F0,X, LDA $10F0,Y and LDA ($F0),Y with $10F0 at $F0, each
+    // indexed to stay on the page and then to cross it
+    let programs: [&[u8]; 3] = [&[0xBD, 0xF0, 0x10], &[0xB9, 0xF0, 0x10], &[0xB1, 0xF0]];
+    for program in programs {
+      for (index, reads) in [(0x05, 1), (0x20, 2)] {
+        let (mut system, bus) = counting_system(program);
+        system.patch(0x00F0, &[0xF0, 0x10]);
+        system.registers.x = index;
+        system.registers.y = index;
+        system.accurate_bus(true);
+
+        system.step().unwrap();
+        assert_eq!(
+          bus.reads.get(),
+          reads,
+          "{:02X} with {:02X}",
+          program[0],
+          index
+        );
+        assert_eq!(system.last_cycle_detail().page_crossed, reads == 2);
+      }
+    }
+  }
+
+  #[test]
+  fn wait_states_are_added_to_the_instruction() {
+    // LDA $10F0,X on the page and across it, then INC $1000, which all
+    // stall for every access to the device whether or not it sees them
+    let cases: [(&[u8], u8, u64); 3] = [
+      (&[0xBD, 0xF0, 0x10], 0x05, 4 + 2),
+      (&[0xBD, 0xF0, 0x10], 0x20, 5 + 2 * 2),
+      (&[0xEE, 0x00, 0x10], 0x00, 6 + 2 * 3),
+    ];
+
+    for (program, x, cycles) in cases {
+      for accurate in [false, true] {
+        let (mut system, bus) = counting_system(program);
+        system.registers.x = x;
+        bus.wait_states.set(2);
+        system.accurate_bus(accurate);
+
+        system.step().unwrap();
+        assert_eq!(system.cycles(), cycles, "{:02X} with {:02X}", program[0], x);
+      }
+    }
+  }
+
+  #[test]
+  fn indirect_y_charges_loads_for_a_page_crossing_and_stores_always() {
+    // LDA ($F0),Y and STA ($F0),Y with $10F0 at $F0
+    let cases = [
+      (0xB1, 0x20, 6),
+      (0xB1, 0x05, 5),
+      (0x91, 0x20, 6),
+      (0x91, 0x05, 6),
+    ];
+
+    for (opcode, y, cycles) in cases {
+      let mut system = system(&[opcode, 0xF0]);
+      system.patch(0x00F0, &[0xF0, 0x10]);
+      system.registers.y = y;
+
+      system.step().unwrap();
+      assert_eq!(system.cycles(), cycles, "{:02X} with Y={:02X}", opcode, y);
+    }
+  }
+
+  #[test]
+  fn jsr_and_rts_wrap_the_return_address() {
+    let mut system = system(&[]);
+    // JSR $0300 from $FFFD, which returns to $0000, and RTS
+    system.patch(0xFFFD, &[0x20, 0x00, 0x03]);
+    system.patch(0x0300, &[0x60]);
+    system.registers.pc.load(0xFFFD);
+    let sp = system.registers.sp.get();
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), 0x0300);
+    assert_eq!(system.peek(0x0100 + sp as u16), 0xFF);
+    assert_eq!(system.peek(0x00FF + sp as u16), 0xFF);
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), 0x0000);
+    assert_eq!(system.registers.sp.get(), sp);
+  }
+
+  #[test]
+  fn words_are_little_endian_and_wrap_at_ffff() {
+    let mut system = system(&[]);
+    system.write_word(0x1234, 0xBEEF);
+    assert_eq!((system.peek(0x1234), system.peek(0x1235)), (0xEF, 0xBE));
+    assert_eq!(system.read_word(0x1234), 0xBEEF);
+
+    system.write_word(0xFFFF, 0xCAFE);
+    assert_eq!((system.peek(0xFFFF), system.peek(0x0000)), (0xFE, 0xCA));
+    assert_eq!(system.read_word(0xFFFF), 0xCAFE);
+    assert_eq!(system.try_read_word(0xFFFF).unwrap(), 0xCAFE);
+  }
+
+  #[test]
+  fn frames_average_out_to_the_clock_rate() {
+    // JMP to itself, taking 3 cycles
+    let mut system = system(&[0x4C, 0x00, 0x02]);
+
+    for _ in 0..FRAME_RATE * 10 {
+      let end = system.next_frame();
+      while system.cycles() < end {
+        system.step().unwrap();
+      }
+    }
+
+    // Ten seconds, overrunning by less than one JMP
+    let ideal = CLOCK_RATE * 10;
+    assert!(
+      (ideal..ideal + 3).contains(&system.cycles()),
+      "{}",
+      system.cycles()
+    );
+  }
+
+  #[test]
+  fn call_subroutine_stops_at_the_matching_rts() {
+    // JSR $0310 and RTS, then an RTS in a subroutine of its own
+    let mut system = system(&[]);
+    system.patch(0x0300, &[0x20, 0x10, 0x03, 0x60]);
+    system.patch(0x0310, &[0x60]);
+    let sp = system.registers.sp.get();
+
+    assert_eq!(system.call_subroutine(0x0300, 10), Ok(true));
+    assert_eq!(system.registers.pc.address(), PROGRAM);
+    assert_eq!(system.registers.sp.get(), sp);
+
+    // JMP to itself never returns
+    system.patch(0x0320, &[0x4C, 0x20, 0x03]);
+    assert_eq!(system.call_subroutine(0x0320, 10), Ok(false));
+  }
+
+  #[test]
+  fn stepping_reads_only_the_instruction() {
+    // Every read takes the next byte, whatever the address: LDA #$42, then
+    // LDA #$43 if nothing else read from the device in between
+    let script = ScriptedMemory::new(&[0xA9, 0x42, 0xA9, 0x43]);
+    let mut system = System::new(Box::new(script.clone()));
+
+    system.step().unwrap();
+    system.step().unwrap();
+    assert_eq!(system.registers.a, 0x43);
+    assert_eq!(script.remaining(), 0);
+  }
+
+  #[test]
+  fn samples_carry_on_from_a_loaded_state() {
+    let mut system = system(&[]);
+    system.nop_fill(PROGRAM, 0x100);
+    let samples = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&samples);
+    // One sample every 10 cycles
+    system.on_sample(
+      1000,
+      100,
+      Box::new(move |cycle| recorded.borrow_mut().push(cycle)),
+    );
+    let state = system.save_state();
+
+    for _ in 0..100 {
+      system.step().unwrap();
+    }
+    assert_eq!(samples.borrow().len(), 20);
+
+    samples.borrow_mut().clear();
+    system.load_state(&state);
+    for _ in 0..20 {
+      system.step().unwrap();
+    }
+    assert_eq!(*samples.borrow(), vec![10, 20, 30, 40]);
+  }
+
+  #[test]
+  fn interrupts_wrap_the_stack_within_page_one() {
+    let mut system = system(&[0xEA]);
+    system.registers.sp.set(0x01);
+    system.irq();
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), IRQ_HANDLER);
+    assert_eq!(system.registers.sp.get(), 0xFE);
+    assert_eq!(system.peek(0x0101), 0x02);
+    assert_eq!(system.peek(0x0100), 0x00);
+    assert_eq!(system.peek(0x01FF), flags::UNUSED);
+  }
+
+  #[test]
+  fn decimal_mode_carries_and_borrows_between_instructions() {
+    #[rustfmt::skip]
+    let mut system = system(&[
+      0xF8, 0x18, 0xA9, 0x19, 0x69, 0x28, // SED, CLC, LDA #$19, ADC #$28
+      0x69, 0x55,                         // ADC #$55
+      0xE9, 0x03,                         // SBC #$03
+      0xD8, 0x18, 0xA9, 0x19, 0x69, 0x28, // CLD, CLC, LDA #$19, ADC #$28
+    ]);
+
+    // The instructions to run, then the accumulator and carry after them
+    for (steps, a, carry) in [
+      (4, 0x47, false),
+      (1, 0x02, true),
+      (1, 0x99, false),
+      (4, 0x41, false),
+    ] {
+      for _ in 0..steps {
+        system.step().unwrap();
+      }
+      assert_eq!(system.registers.a, a);
+      assert_eq!(
+        system.registers.sr.read(flags::CARRY),
+        carry,
+        "after {:02X}",
+        a
+      );
+    }
+  }
+
+  #[test]
+  fn read_modify_write_writes_the_original_then_the_result() {
+    // ASL, LSR, ROL and ROR of $1000,X with X=1, on an accurate bus, with the
+    // carry clear
+    for (opcode, result) in [(0x1E, 0x82), (0x5E, 0x20), (0x3E, 0x82), (0x7E, 0x20)] {
+      let (mut system, bus) = counting_system(&[opcode, 0x00, 0x10]);
+      system.patch(0x1001, &[0x41]);
+      bus.written.borrow_mut().clear();
+      system.registers.x = 0x01;
+      system.registers.sr.clear(flags::CARRY);
+      system.accurate_bus(true);
+
+      system.step().unwrap();
+      assert_eq!(bus.reads.get(), 1, "{:02X}", opcode);
+      assert_eq!(*bus.written.borrow(), vec![0x41, result], "{:02X}", opcode);
+    }
+  }
+
+  #[test]
+  fn fetches_wrap_from_ffff_to_0000() {
+    // LDA #$5A, with the opcode at $FFFF and the operand at $0000
+    let mut system = system(&[]);
+    system.patch(0xFFFF, &[0xA9]);
+    system.patch(0x0000, &[0x5A]);
+    system.registers.pc.load(0xFFFF);
+
+    system.step().unwrap();
+    assert_eq!(system.registers.a, 0x5A);
+    assert_eq!(system.registers.pc.address(), 0x0001);
+
+    // JMP $8010 from $FFFE, with the high byte of the target at $0000
+    system.patch(0xFFFE, &[0x4C, 0x10]);
+    system.patch(0x0000, &[0x80]);
+    system.registers.pc.load(0xFFFE);
+
+    system.step().unwrap();
+    assert_eq!(system.registers.pc.address(), 0x8010);
+  }
+
+  #[test]
+  fn indexed_stores_wrap() {
+    // The store, its index, and where it should land
+    let cases: [(&[u8], u8, u16); 5] = [
+      (&[0x9D, 0xFF, 0xFF], 0x01, 0x0000), // STA $FFFF,X
+      (&[0x99, 0xFF, 0xFF], 0x04, 0x0003), // STA $FFFF,Y
+      (&[0x95, 0xFF], 0x02, 0x0001),       // STA $FF,X, staying in the zero page
+      (&[0x91, 0xFF], 0x01, 0x0211),       // STA ($FF),Y, with $0210 at $FF/$00
+      (&[0x81, 0xFF], 0x00, 0x0210),       // STA ($FF,X)
+    ];
+
+    for (program, index, address) in cases {
+      let mut system = system(program);
+      system.patch(0x00FF, &[0x10]);
+      system.patch(0x0000, &[0x02]);
+      system.registers.x = index;
+      system.registers.y = index;
+      system.registers.a = 0x5A;
+
+      system.step().unwrap();
+      assert_eq!(system.peek(address), 0x5A, "{:02X}", program[0]);
+    }
   }
 }
@@ -0,0 +1,189 @@
+use crate::disassembler::disassemble;
+use crate::opcodes::length;
+use crate::system::System;
+use std::fmt;
+
+// The state before an instruction executes, as recorded in reference logs
+// like nestest.log, whose lines look like:
+//   C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TraceLine {
+  pub pc: u16,
+  pub opcode: u8,
+  pub a: u8,
+  pub x: u8,
+  pub y: u8,
+  pub p: u8,
+  pub sp: u8,
+  pub cycles: Option<u64>, // not every log has them
+}
+
+impl TraceLine {
+  pub fn capture(system: &System) -> Self {
+    let registers = &system.registers;
+
+    Self {
+      pc: registers.pc.address(),
+      opcode: system.peek(registers.pc.address()),
+      a: registers.a,
+      x: registers.x,
+      y: registers.y,
+      p: registers.sr.get(),
+      sp: registers.sp.get(),
+      cycles: Some(system.cycles()),
+    }
+  }
+
+  // Only the address, the opcode and the labelled fields are read, so the
+  // disassembly and anything emulator-specific (like PPU:) can be anything
+  pub fn parse(line: &str) -> Option<Self> {
+    let mut words = line.split_whitespace();
+    let pc = u16::from_str_radix(words.next()?, 16).ok()?;
+    let opcode = u8::from_str_radix(words.next()?, 16).ok()?;
+
+    let field = |name: &str| {
+      line
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix(name))
+        .and_then(|value| u8::from_str_radix(value, 16).ok())
+    };
+
+    let cycles = line
+      .split_whitespace()
+      .find_map(|word| word.strip_prefix("CYC:"))
+      .and_then(|value| value.parse().ok());
+
+    Some(Self {
+      pc,
+      opcode,
+      a: field("A:")?,
+      x: field("X:")?,
+      y: field("Y:")?,
+      p: field("P:")?,
+      sp: field("SP:")?,
+      cycles,
+    })
+  }
+
+  // The names of the fields that don't match. Cycle counts are compared
+  // relative to each trace's first line, and the B and unused flags are
+  // ignored, since they aren't real bits of the status register and logs
+  // disagree on how to show them.
+  fn differences(&self, other: &TraceLine, cycles: u64, other_cycles: u64) -> Vec<&'static str> {
+    let flags = |p: u8| p & 0b11001111;
+
+    [
+      ("PC", self.pc != other.pc),
+      ("opcode", self.opcode != other.opcode),
+      ("A", self.a != other.a),
+      ("X", self.x != other.x),
+      ("Y", self.y != other.y),
+      ("P", flags(self.p) != flags(other.p)),
+      ("SP", self.sp != other.sp),
+      ("CYC", cycles != other_cycles),
+    ]
+    .into_iter()
+    .filter(|&(_, differs)| differs)
+    .map(|(name, _)| name)
+    .collect()
+  }
+}
+
+impl fmt::Display for TraceLine {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+      self.pc, self.opcode, self.a, self.x, self.y, self.p, self.sp
+    )?;
+
+    match self.cycles {
+      Some(cycles) => write!(f, " CYC:{}", cycles),
+      None => Ok(()),
+    }
+  }
+}
+
+// A full trace line for the system's next instruction, in the same format
+// as the reference logs
+pub fn trace(system: &System) -> String {
+  let line = TraceLine::capture(system);
+  let length = length(line.opcode);
+
+  let mut bytes = [0; 3];
+  for (offset, byte) in bytes.iter_mut().enumerate().take(length as usize) {
+    *byte = system.peek(line.pc.wrapping_add(offset as u16));
+  }
+
+  let hex: Vec<String> = bytes[..length as usize]
+    .iter()
+    .map(|byte| format!("{:02X}", byte))
+    .collect();
+  let (text, _) = disassemble(line.pc, bytes);
+
+  format!(
+    "{:04X}  {:8}  {:30}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+    line.pc,
+    hex.join(" "),
+    text,
+    line.a,
+    line.x,
+    line.y,
+    line.p,
+    line.sp,
+    system.cycles()
+  )
+}
+
+// Run the system in lock-step with a reference log, checking its state
+// before each instruction against the log's line and stopping at the first
+// that differs. The registers start out as the log's first line has them.
+// Returns the number of lines matched.
+pub fn compare(system: &mut System, log: &str) -> Result<usize, String> {
+  let mut start = None;
+
+  for (index, text) in log.lines().enumerate() {
+    if text.trim().is_empty() {
+      continue;
+    }
+
+    let number = index + 1;
+    let expected =
+      TraceLine::parse(text).ok_or_else(|| format!("Couldn't parse line {}: {}", number, text))?;
+
+    let (first, first_cycles) = *start.get_or_insert_with(|| {
+      let registers = &mut system.registers;
+      registers.pc.load(expected.pc);
+      registers.a = expected.a;
+      registers.x = expected.x;
+      registers.y = expected.y;
+      registers.sr.load(expected.p);
+      registers.sp.set(expected.sp);
+      (expected.cycles, system.cycles())
+    });
+
+    let actual = TraceLine::capture(system);
+    let elapsed = system.cycles() - first_cycles;
+    let expected_elapsed = match (expected.cycles, first) {
+      (Some(cycles), Some(first)) => cycles.wrapping_sub(first),
+      _ => elapsed, // nothing to compare against
+    };
+
+    let differences = expected.differences(&actual, expected_elapsed, elapsed);
+    if !differences.is_empty() {
+      return Err(format!(
+        "Diverged at line {} ({}):\n  expected {}\n  got      {}",
+        number,
+        differences.join(", "),
+        expected,
+        actual
+      ));
+    }
+
+    system
+      .try_tick()
+      .map_err(|error| format!("Stopped at line {}: {}", number, error))?;
+  }
+
+  Ok(log.lines().filter(|line| !line.trim().is_empty()).count())
+}
@@ -0,0 +1,53 @@
+// Detects programs spinning in a tight loop, like polling for a condition
+// that will never become true
+
+// Largest span of addresses still considered a single loop
+const LOOP_SIZE: u16 = 32;
+
+pub struct Watchdog {
+  limit: u64,
+  start: u16,
+  end: u16,
+  count: u64,
+}
+
+impl Watchdog {
+  pub fn new(limit: u64) -> Self {
+    Self {
+      limit,
+      start: 0,
+      end: 0,
+      count: 0,
+    }
+  }
+
+  // Called with the PC before each instruction, returns true once the loop
+  // has run for more than the limit without making progress
+  pub fn visit(&mut self, pc: u16) -> bool {
+    let start = self.start.min(pc);
+    let end = self.end.max(pc);
+
+    if end - start < LOOP_SIZE {
+      self.start = start;
+      self.end = end;
+      self.count += 1;
+    } else {
+      self.start = pc;
+      self.end = pc;
+      self.count = 0;
+    }
+
+    self.count > self.limit
+  }
+
+  // Writes outside of the loop's own code count as progress
+  pub fn write(&mut self, address: u16) {
+    if address < self.start || address > self.end {
+      self.count = 0;
+    }
+  }
+
+  pub fn range(&self) -> (u16, u16) {
+    (self.start, self.end)
+  }
+}
@@ -0,0 +1,29 @@
+use noentiendo::memory::BlockMemory;
+use noentiendo::{trace, System};
+
+const LOG: &str = include_str!("data/count.log");
+
+// LDA #$01, LDX #$02, INX, STA $10, JMP $C000
+const PROGRAM: [u8; 10] = [0xA9, 0x01, 0xA2, 0x02, 0xE8, 0x85, 0x10, 0x4C, 0x00, 0xC0];
+
+fn system() -> System {
+  let mut system = System::new(Box::new(BlockMemory::ram(0x10000)));
+  system.patch(0xC000, &PROGRAM);
+  system
+}
+
+#[test]
+fn matches_the_reference_log() {
+  assert_eq!(trace::compare(&mut system(), LOG), Ok(6));
+}
+
+#[test]
+fn stops_where_the_log_diverges() {
+  let log = LOG.replacen(
+    "A:01 X:03 Y:00 P:24 SP:FD CYC:13",
+    "A:01 X:04 Y:00 P:24 SP:FD CYC:13",
+    1,
+  );
+  let error = trace::compare(&mut system(), &log).unwrap_err();
+  assert!(error.starts_with("Diverged at line 4 (X)"), "{}", error);
+}